@@ -4,7 +4,7 @@ use libplugin::{debug, spawn, Socket};
 use rand::distributions::{Distribution, Uniform};
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
-use render::{Id, Isometry2, ObjectData, Point2, Point3, RendererConn, Vector2, Rotation2};
+use render::{Id, Isometry2, ObjectData, Point2, Point3, RendererClient, Vector2, Rotation2};
 use std::any::{Any, TypeId};
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
@@ -107,9 +107,9 @@ struct Renderable {
 }
 
 impl Renderable {
-    pub async fn new(renderer: &mut RendererConn<Socket>, shape: ObjectData) -> Self {
+    pub async fn new(renderer: &mut RendererClient, shape: ObjectData) -> Self {
         let transform = shape.transform;
-        let id = renderer.add_object(shape).await;
+        let id = renderer.add_object(shape).await.expect("Renderer hung up");
         Self {
             id,
             transform,
@@ -119,19 +119,19 @@ impl Renderable {
 
     pub async fn set_transform(
         &mut self,
-        renderer: &mut RendererConn<Socket>,
+        renderer: &mut RendererClient,
         transform: Isometry2<f32>,
     ) {
         self.transform = transform;
-        renderer.set_transform(self.id, transform).await;
+        renderer.set_transform(self.id, transform).expect("Renderer hung up");
     }
 
     pub fn get_transform(&self) -> Isometry2<f32> {
         self.transform
     }
 
-    pub async fn delete(mut self, renderer: &mut RendererConn<Socket>) {
-        renderer.delete_object(self.id).await;
+    pub async fn delete(mut self, renderer: &mut RendererClient) {
+        renderer.delete_object(self.id).expect("Renderer hung up");
         self.deleted = true;
     }
 }
@@ -146,7 +146,7 @@ impl Drop for Renderable {
 
 struct Velocity(pub Vector2<f32>);
 
-async fn physics_system(ecs: &mut ECData, renderer: &mut RendererConn<Socket>, bounds: &Box2D) {
+async fn physics_system(ecs: &mut ECData, renderer: &mut RendererClient, bounds: &Box2D) {
     let entities = ecs.with(&[TypeId::of::<Renderable>(), TypeId::of::<Velocity>()]);
     for entity in &entities {
         let velocity = ecs.get_component::<Velocity>(entity).unwrap().0;
@@ -203,19 +203,16 @@ impl Box2D {
     }
 }
 
-#[no_mangle]
-pub extern "C" fn main() {
+#[libplugin::main]
+async fn main() {
     debug("Asteroids init!");
-    std::panic::set_hook(Box::new(|info| {
-        debug(&info.to_string());
-    }));
-    spawn(asteroids());
+    asteroids().await
 }
 
 struct Ship;
 
 impl Ship {
-    pub async fn new(ecs: &mut ECData, renderer: &mut RendererConn<Socket>) -> EntityId {
+    pub async fn new(ecs: &mut ECData, renderer: &mut RendererClient) -> EntityId {
         let id = ecs.new_entity();
         let shape = render::ObjectData::new(objects::rocket(), Isometry2::identity());
         ecs.add_component(&id, Renderable::new(renderer, shape).await);
@@ -226,7 +223,7 @@ impl Ship {
 
     pub async fn system(
         ecs: &mut ECData,
-        renderer: &mut RendererConn<Socket>,
+        renderer: &mut RendererClient,
         fire_engine: bool,
         left_key: bool,
         right_key: bool,
@@ -285,7 +282,7 @@ struct Bullet {
 impl Bullet {
     pub async fn new(
         ecs: &mut ECData,
-        renderer: &mut RendererConn<Socket>,
+        renderer: &mut RendererClient,
         position: Point2<f32>,
         velocity: Vector2<f32>,
     ) -> EntityId {
@@ -300,7 +297,7 @@ impl Bullet {
         id
     }
 
-    pub async fn system(ecs: &mut ECData, renderer: &mut RendererConn<Socket>) {
+    pub async fn system(ecs: &mut ECData, renderer: &mut RendererClient) {
         let bullets = ecs.with(&[
             TypeId::of::<Bullet>(),
             TypeId::of::<Renderable>(),
@@ -323,7 +320,12 @@ async fn asteroids() {
     let mut ecs = ECData::new();
     let socket = Socket::connect("renderer", 0).unwrap().await.unwrap();
     debug("Connected to renderer");
-    let mut renderer = RendererConn::new(socket);
+    let (mut renderer, driver) = RendererClient::new(rpc::framed(socket));
+    spawn(async move {
+        if let Err(e) = driver.await {
+            debug(format!("Renderer connection failed: {}", e));
+        }
+    });
 
     let screen = Box2D::new(Point2::new(-360.0, -360.0), Point2::new(360.0, 360.0));
     let _screen_rect = renderer
@@ -331,11 +333,12 @@ async fn asteroids() {
             data: objects::rectangle(&screen, Point3::new(1.0, 1.0, 1.0)),
             transform: Isometry2::identity(),
         })
-        .await;
+        .await
+        .expect("Renderer hung up");
 
     Ship::new(&mut ecs, &mut renderer).await;
     loop {
-        let info = renderer.wait_frame().await;
+        let info = renderer.wait_frame().await.expect("Renderer hung up");
         Ship::system(
             &mut ecs,
             &mut renderer,
@@ -427,7 +430,7 @@ pub fn collides_with(&self, object: Point2<f32>) -> bool {
 }
 }
 
-    let mut rng = SmallRng::seed_from_u64(3489277);
+    let mut rng = SmallRng::seed_from_u64(libplugin::random::u64());
     let pos = Uniform::new(-300.9, 300.0);
     let size = Uniform::new(10.0, 60.0);
     let vel = Uniform::new(0.1, 1.0);
@@ -468,7 +471,7 @@ pub fn collides_with(&self, object: Point2<f32>) -> bool {
             }
             for bullet in &mut bullets {
                 if asteroid.collides_with(bullet.position) {
-                    debug(&format!("HIT {} {}", bullet.position.x, bullet.position.y));
+                    debug(format!("HIT {} {}", bullet.position.x, bullet.position.y));
                     bullet.duration = 0;
                     renderer.delete_object(bullet.render_id).await;
                     asteroid.life -= 1;
@@ -0,0 +1,116 @@
+use anyhow::{format_err, Context, Result};
+use simplelog::{CombinedLogger, LevelFilter, SharedLogger, TermLogger, TerminalMode, WriteLogger};
+use std::fs::File;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Runs game modules, and the services they use to talk to each other
+#[derive(Debug, StructOpt)]
+#[structopt(name = "host")]
+pub struct Opt {
+    /// Directory to load modules from. May be given more than once.
+    #[structopt(long = "mods", parse(from_os_str))]
+    pub mods_dirs: Vec<PathBuf>,
+
+    /// Load this module file. If any are given, module directories are only searched when given
+    /// explicitly with --mods.
+    #[structopt(short, long = "module", parse(from_os_str))]
+    pub modules: Vec<PathBuf>,
+
+    /// Kernel configuration file
+    #[structopt(long, default_value = "../kernel.toml", parse(from_os_str))]
+    pub config: PathBuf,
+
+    /// Run without the vector graphics renderer
+    #[structopt(long)]
+    pub headless: bool,
+
+    /// Draw vector graphics in software rather than in a window, for machines without a display.
+    /// Always the case when built without the `window` feature.
+    #[structopt(long)]
+    pub software_render: bool,
+
+    /// Frames per second of the software renderer's virtual clock
+    #[structopt(long, default_value = "60")]
+    pub frame_rate: u32,
+
+    /// Script of keys for the software renderer to report as held
+    #[structopt(long, parse(from_os_str))]
+    pub key_script: Option<PathBuf>,
+
+    /// Directory to save frames in, as SVG and PNG, when a module asks or F12 is pressed in the
+    /// window
+    #[structopt(long, default_value = "captures", parse(from_os_str))]
+    pub capture_dir: PathBuf,
+
+    /// Also save every this many frames
+    #[structopt(long)]
+    pub capture_every: Option<u64>,
+
+    /// Exit once every module has finished all of its tasks
+    #[structopt(long)]
+    pub once: bool,
+
+    /// Log level: off, error, warn, info, debug or trace
+    #[structopt(long, default_value = "info")]
+    pub log_level: LevelFilter,
+
+    /// Serve per-module metrics in the Prometheus text format on this localhost port
+    #[structopt(long)]
+    pub metrics_port: Option<u16>,
+
+    /// Also write the log to this file
+    #[structopt(long, parse(from_os_str))]
+    pub log_file: Option<PathBuf>,
+
+    /// Stop a module whose memory has grown past this, as MODULE=PAGES (64KiB each). Checked
+    /// after each call into the module. Overrides the config file.
+    #[structopt(long = "memory-check", parse(try_from_str = parse_per_module))]
+    pub memory_check: Vec<(String, u64)>,
+
+    /// Stop a module if a call into it took longer than this, as MODULE=MILLISECONDS. Checked
+    /// after the call returns, so a module which never returns is not stopped. Overrides the
+    /// config file.
+    #[structopt(long = "run-time-check", parse(try_from_str = parse_per_module))]
+    pub run_time_check: Vec<(String, u64)>,
+
+    /// Seed modules' randomness, so that runs can be reproduced. Overrides the config file.
+    #[structopt(long)]
+    pub seed: Option<u64>,
+}
+
+impl Opt {
+    /// Directories to search for modules
+    pub fn mods_dirs(&self) -> Vec<PathBuf> {
+        if self.mods_dirs.is_empty() && self.modules.is_empty() {
+            vec!["../mods".into()]
+        } else {
+            self.mods_dirs.clone()
+        }
+    }
+
+    /// Set up logging to the terminal, and the log file if there is one
+    pub fn init_logging(&self) -> Result<()> {
+        let config = simplelog::Config::default();
+        let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
+            self.log_level,
+            config.clone(),
+            TerminalMode::Mixed,
+        )];
+        if let Some(path) = &self.log_file {
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create log file {}", path.display()))?;
+            loggers.push(WriteLogger::new(self.log_level, config, file));
+        }
+        CombinedLogger::init(loggers)?;
+        Ok(())
+    }
+}
+
+fn parse_per_module(s: &str) -> Result<(String, u64)> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(module), Some(value)) if !module.is_empty() => Ok((module.into(), value.parse()?)),
+        _ => Err(format_err!("Expected MODULE=VALUE, got \"{}\"", s)),
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{bail, Context, Result};
+use loopback::{Faults, LoopbackConfig};
+use protocols::{ModuleId, Port};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::time::Duration;
+
+/// Kernel configuration, loaded from a TOML file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Per-module settings, keyed by the module's file stem
+    pub modules: HashMap<String, ModuleConfig>,
+    /// Seed for every module's randomness, so that runs can be reproduced. Without one, modules
+    /// get randomness from the operating system.
+    pub seed: Option<u64>,
+}
+
+/// Settings for a single module binary
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ModuleConfig {
+    /// Number of instances of this module to run
+    pub instances: usize,
+    /// Services which must be listening before this module's `main` is called
+    pub depends: Vec<Dependency>,
+    /// Module-specific settings, passed to the module's `init` export as TOML text
+    pub config: toml::value::Table,
+    /// Stop the module if its memory has grown past this many 64KiB pages. Checked after each
+    /// call into the module, so memory can briefly exceed it during a call.
+    pub memory_check_pages: Option<u64>,
+    /// Stop the module if a call into it took longer than this. Checked after the call returns,
+    /// so a module which never returns is not stopped.
+    pub run_time_check_ms: Option<u64>,
+    /// Bytes buffered in each direction of connections to this module's listeners
+    pub buffer_capacity: Option<usize>,
+    /// Bytes held back before connections to this module's listeners wake the reader
+    pub flush_threshold: Option<usize>,
+    /// Simulate bad conditions on this module's connections
+    pub faults: Option<FaultConfig>,
+    /// Seed for this module's randomness, instead of the kernel-wide seed
+    pub seed: Option<u64>,
+}
+
+/// Bad conditions to simulate on a module's connections
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FaultConfig {
+    /// Random seed, so that runs can be reproduced
+    pub seed: u64,
+    /// Time data spends in transit
+    pub latency_ms: u64,
+    /// Split writes into random chunks of at most this many bytes
+    pub max_chunk: Option<usize>,
+    /// Bytes per second
+    pub bandwidth: Option<u64>,
+    /// Start failing with NotConnected once this many bytes have been transferred
+    pub fail_after: Option<u64>,
+    /// Chance of failing on each read or write after `fail_after` bytes
+    pub fail_chance: f64,
+    /// Chance of stalling on each read or write
+    pub stall_chance: f64,
+    pub stall_ms: u64,
+}
+
+/// A service provided by another module
+#[derive(Debug, Clone, Deserialize)]
+pub struct Dependency {
+    pub module: ModuleId,
+    #[serde(default)]
+    pub port: Port,
+}
+
+impl Default for ModuleConfig {
+    fn default() -> Self {
+        Self {
+            instances: 1,
+            depends: Vec::new(),
+            config: Default::default(),
+            memory_check_pages: None,
+            run_time_check_ms: None,
+            buffer_capacity: None,
+            flush_threshold: None,
+            faults: None,
+            seed: None,
+        }
+    }
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            latency_ms: 0,
+            max_chunk: None,
+            bandwidth: None,
+            fail_after: None,
+            fail_chance: 1.0,
+            stall_chance: 0.0,
+            stall_ms: 0,
+        }
+    }
+}
+
+impl FaultConfig {
+    pub fn faults(&self) -> Faults {
+        Faults {
+            seed: self.seed,
+            latency: Duration::from_millis(self.latency_ms),
+            max_chunk: self.max_chunk,
+            bandwidth: self.bandwidth,
+            fail_after: self.fail_after,
+            fail_chance: self.fail_chance,
+            stall_chance: self.stall_chance,
+            stall: Duration::from_millis(self.stall_ms),
+        }
+    }
+}
+
+impl ModuleConfig {
+    /// Settings for connections made to this module's listeners
+    pub fn loopback_config(&self) -> LoopbackConfig {
+        let default = LoopbackConfig::default();
+        LoopbackConfig {
+            capacity: self.buffer_capacity.unwrap_or(default.capacity),
+            flush_threshold: self.flush_threshold.unwrap_or(default.flush_threshold),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config at `path`, or the default config if there is no such file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match read_to_string(path) {
+            Ok(text) => {
+                let config: Self = toml::from_str(&text)
+                    .with_context(|| format!("Invalid config file {}", path.display()))?;
+                config.check_dependencies()?;
+                Ok(config)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Settings for the module named `name`, which may be changed
+    pub fn module_mut(&mut self, name: &str) -> &mut ModuleConfig {
+        self.modules.entry(name.into()).or_default()
+    }
+
+    /// Settings for the module named `name`
+    pub fn module(&self, name: &str) -> ModuleConfig {
+        let mut module = self.modules.get(name).cloned().unwrap_or_default();
+        module.seed = module.seed.or(self.seed);
+        module
+    }
+
+    /// Ids of each instance of the module named `name`. A single instance keeps the plain name,
+    /// so that other modules can keep connecting to it by that name.
+    pub fn instance_ids(&self, name: &str) -> Vec<ModuleId> {
+        match self.module(name).instances {
+            1 => vec![name.into()],
+            n => (1..=n).map(|i| format!("{}#{}", name, i)).collect(),
+        }
+    }
+
+    /// Make sure no modules depend on each other in a cycle, which would never start up
+    fn check_dependencies(&self) -> Result<()> {
+        fn visit<'a>(config: &'a Config, name: &'a str, path: &mut Vec<&'a str>) -> Result<()> {
+            if path.contains(&name) {
+                path.push(name);
+                bail!("Module dependency cycle: {}", path.join(" -> "));
+            }
+            path.push(name);
+            if let Some(module) = config.modules.get(name) {
+                for dep in &module.depends {
+                    // Instances such as `name#2` share the dependencies of `name`
+                    let dep_name = dep.module.split('#').next().unwrap();
+                    visit(config, dep_name, path)?;
+                }
+            }
+            path.pop();
+            Ok(())
+        }
+
+        for name in self.modules.keys() {
+            visit(self, name, &mut Vec::new())?;
+        }
+        Ok(())
+    }
+}
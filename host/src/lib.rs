@@ -1,2 +1,4 @@
-pub mod matchmaker;
-pub mod socket;
+pub use kernel::{matchmaker, metrics, socket};
+pub mod config;
+pub mod lifecycle;
+pub mod wasm_module;
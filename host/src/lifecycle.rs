@@ -0,0 +1,50 @@
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use protocols::ModuleId;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Lifecycle events delivered to every running module
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The module with this id has stopped
+    PeerDown(ModuleId),
+    /// The kernel is shutting down; modules have the given grace period to finish up
+    Shutdown(Duration),
+}
+
+/// Broadcasts lifecycle events to module tasks
+#[derive(Clone, Default)]
+pub struct Lifecycle {
+    subscribers: Arc<Mutex<Vec<UnboundedSender<Event>>>>,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive all future lifecycle events
+    pub fn subscribe(&self) -> UnboundedReceiver<Event> {
+        let (tx, rx) = unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Notify all modules that `id` has stopped
+    pub fn peer_down(&self, id: ModuleId) {
+        self.broadcast(Event::PeerDown(id));
+    }
+
+    /// Notify all modules that the kernel is shutting down
+    pub fn shutdown(&self, grace: Duration) {
+        self.broadcast(Event::Shutdown(grace));
+    }
+
+    fn broadcast(&self, event: Event) {
+        // Subscribers which have hung up are forgotten
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|sub| sub.unbounded_send(event.clone()).is_ok());
+    }
+}
@@ -1,26 +1,40 @@
 #![allow(unused_imports)]
-mod wasm_module;
-use anyhow::{format_err, Result};
-use futures::executor::ThreadPool;
+mod cli;
+use anyhow::{format_err, Context, Result};
+use cli::Opt;
+use futures::channel::oneshot;
+use futures::executor::{block_on, ThreadPool};
+use futures::future::{join_all, select, Either, RemoteHandle};
+use futures::lock::Mutex;
 use futures::task::SpawnExt;
-use futures::{SinkExt, StreamExt};
+use futures::{FutureExt, SinkExt, StreamExt};
+use host::config::Config;
+use host::lifecycle::Lifecycle;
 use host::matchmaker::{self, MatchMakerConnection};
+use host::metrics::Metrics;
+use host::wasm_module::WasmModule;
+use log::{error, info, warn};
+use loopback::LoopbackConfig;
 use std::error::Error;
 use std::fs::{create_dir, read_dir};
-use std::path::Path;
-use wasm_module::WasmModule;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use structopt::StructOpt;
 
-fn load_mods(
-    folder: impl AsRef<Path>,
-    executor: &impl SpawnExt,
-    matchmaker: MatchMakerConnection,
-) -> Result<()> {
-    let mods_folder = read_dir(&folder);
+/// Time modules are given to finish up after a shutdown is requested
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Find the module files in a mods folder
+fn module_paths(folder: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mods_folder = read_dir(folder);
     if let Err(e) = &mods_folder {
         if e.kind() == std::io::ErrorKind::NotFound {
-            eprintln!("Mods folder not found; created.");
+            warn!("Mods folder {} not found; created.", folder.display());
             create_dir(folder)?;
-            return Ok(());
+            return Ok(paths);
         }
     }
 
@@ -35,42 +49,215 @@ fn load_mods(
         } else {
             continue;
         };
-
-        let id = path.file_stem().unwrap().to_str().unwrap().into();
-        executor.spawn(WasmModule::from_path(path)?.task(id, matchmaker.clone()))?;
+        paths.push(path);
     }
 
-    Ok(())
+    Ok(paths)
+}
+
+fn load_module(
+    path: &Path,
+    config: &Config,
+    executor: &impl SpawnExt,
+    matchmaker: &MatchMakerConnection,
+    lifecycle: &Lifecycle,
+    metrics: &Metrics,
+) -> Result<Vec<RemoteHandle<bool>>> {
+    let name = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| format_err!("Module file name is not valid UTF-8"))?;
+
+    // Compile once, and share the compiled code between all instances of the module
+    let module = WasmModule::compile_path(&path)?;
+    let mut handles = Vec::new();
+    for id in config.instance_ids(name) {
+        let instance = WasmModule::new(&module, metrics.register(id.clone()))
+            .with_context(|| format!("Failed to instantiate {}", id))?;
+        let task = instance.task(
+            id.clone(),
+            config.module(name),
+            matchmaker.clone(),
+            // Subscribed before the task is spawned, so no event sent in the meantime is missed
+            lifecycle.subscribe(),
+        );
+        let lifecycle = lifecycle.clone();
+        info!("Loaded module {}", id);
+        // Resolves to true if the module crashed
+        handles.push(executor.spawn_with_handle(async move {
+            let result = task.await;
+            match &result {
+                Ok(()) => info!("Module {} finished", id),
+                Err(e) => error!("Module {} stopped: {:?}", id, e),
+            }
+            lifecycle.peer_down(id);
+            result.is_err()
+        })?);
+    }
+    Ok(handles)
 }
 
 fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    opt.init_logging()?;
+
+    let mut config = Config::load(&opt.config)?;
+    for (name, pages) in &opt.memory_check {
+        config.module_mut(name).memory_check_pages = Some(*pages);
+    }
+    for (name, ms) in &opt.run_time_check {
+        config.module_mut(name).run_time_check_ms = Some(*ms);
+    }
+    if opt.seed.is_some() {
+        config.seed = opt.seed;
+    }
+
     // Set up the thread pool and essential tasks
     let spawner = ThreadPool::new()?;
-    let (mm, tx) = matchmaker::MatchMaker::new();
+    let (mut mm, tx) = matchmaker::MatchMaker::new();
+    for (name, module) in &config.modules {
+        if let Some(faults) = &module.faults {
+            for id in config.instance_ids(name) {
+                warn!("Injecting faults into the connections of {}", id);
+                mm.inject_faults(id, faults.faults());
+            }
+        }
+    }
     spawner.spawn(mm.task())?;
 
-    // Load user-written mods
-    println!("Loading mods...");
-    load_mods("../mods", &spawner, tx.clone())?;
+    // Load user-written mods. A module which fails to load doesn't stop the others.
+    info!("Loading mods...");
+    let mut paths = opt.modules.clone();
+    for dir in opt.mods_dirs() {
+        paths.extend(module_paths(&dir)?);
+    }
+    let lifecycle = Lifecycle::new();
+    let metrics = Metrics::new();
+    let mut modules = Vec::new();
+    for path in paths {
+        match load_module(&path, &config, &spawner, &tx, &lifecycle, &metrics) {
+            Ok(handles) => modules.extend(handles),
+            Err(e) => error!("Failed to load module {}: {:?}", path.display(), e),
+        }
+    }
+    if modules.is_empty() {
+        warn!("No modules loaded");
+    }
+
+    if let Some(port) = opt.metrics_port {
+        metrics
+            .serve(port)
+            .with_context(|| format!("Failed to serve metrics on port {}", port))?;
+        info!("Serving metrics on http://localhost:{}/metrics", port);
+    }
 
     // Spawn native-code tasks
-    spawner.spawn(vg_server(tx.clone(), spawner.clone()))?;
+    let renderer = if opt.headless {
+        None
+    } else {
+        let renderer = start_renderer(&opt)?;
+        block_on(renderer.lock()).set_capture(render::CaptureConfig {
+            dir: opt.capture_dir.clone(),
+            every: opt.capture_every,
+            ..Default::default()
+        });
+        spawner.spawn(vg_server(renderer.clone(), tx.clone(), spawner.clone()))?;
+        Some(renderer)
+    };
+
+    // Let the executor take over until we're asked to stop, or with --once until every module
+    // has finished by itself
+    let (signal_tx, signal_rx) = oneshot::channel();
+    let mut signal_tx = Some(signal_tx);
+    ctrlc::set_handler(move || {
+        if let Some(tx) = signal_tx.take() {
+            let _ = tx.send(());
+        }
+    })?;
+
+    let modules = join_all(modules).shared();
+    let crashed = block_on(async {
+        if opt.once {
+            let _ = select(signal_rx, modules.clone()).await;
+        } else {
+            let _ = signal_rx.await;
+        }
+        info!("Shutting down...");
+        shutdown(modules, tx, renderer, &lifecycle).await
+    });
+    std::process::exit(if crashed { 1 } else { 0 })
+}
 
-    // Let the executor take over from here
-    Ok(std::thread::park())
+/// Start the vector graphics renderer, in a window unless asked to draw in software
+fn start_renderer(opt: &Opt) -> Result<Arc<Mutex<render::Renderer>>> {
+    #[cfg(feature = "window")]
+    {
+        if !opt.software_render {
+            return Ok(render::Renderer::new("Game Kernel Vector Graphics".into()));
+        }
+    }
+
+    let keys = match &opt.key_script {
+        Some(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read key script {}", path.display()))?
+            .parse()
+            .with_context(|| format!("Invalid key script {}", path.display()))?,
+        None => Default::default(),
+    };
+    info!(
+        "Drawing vector graphics in software at {} frames per second",
+        opt.frame_rate
+    );
+    Ok(render::Renderer::headless(render::HeadlessConfig {
+        frame_rate: opt.frame_rate,
+        keys,
+    }))
+}
+
+/// Give modules a grace period to finish up, then stop the match maker and renderer. Returns true
+/// if any module crashed or had to be abandoned.
+async fn shutdown(
+    modules: impl Future<Output = Vec<bool>> + Unpin,
+    mut mm: MatchMakerConnection,
+    renderer: Option<Arc<Mutex<render::Renderer>>>,
+    lifecycle: &Lifecycle,
+) -> bool {
+    lifecycle.shutdown(SHUTDOWN_GRACE);
+
+    // Modules stuck inside a call can't be interrupted, so stop waiting for them eventually
+    let deadline = futures_timer::Delay::new(SHUTDOWN_GRACE * 2);
+    let crashed = match select(modules, deadline).await {
+        Either::Left((results, _)) => results.into_iter().any(|crashed| crashed),
+        Either::Right(_) => {
+            error!("Some modules did not stop in time");
+            true
+        }
+    };
+
+    mm.close_channel();
+    if let Some(renderer) = renderer {
+        render::Renderer::close(renderer).await;
+    }
+    crashed
 }
 
-async fn vg_server(mut mm: matchmaker::MatchMakerConnection, spawner: ThreadPool) {
-    use tokio_util::codec::{Framed, LengthDelimitedCodec};
-    use tokio_util::compat::FuturesAsyncReadCompatExt;
-    let renderer = render::Renderer::new("Game Kernel Vector Graphics".into());
-    let mut conn = matchmaker::create_listener("renderer", 0, &mut mm)
+async fn vg_server(
+    renderer: Arc<Mutex<render::Renderer>>,
+    mut mm: matchmaker::MatchMakerConnection,
+    spawner: ThreadPool,
+) {
+    // Clients stream many small transform messages, so let them batch up
+    let config = LoopbackConfig {
+        capacity: 65536,
+        flush_threshold: 4096,
+    };
+    let mut conn = matchmaker::create_typed_listener("renderer", 0, config, &mut mm)
         .await
         .unwrap();
-    while let Some(socket) = conn.next().await {
+    while let Some(channel) = conn.next().await {
         let renderer = renderer.clone();
         spawner
-            .spawn(render::Renderer::handle_client(renderer.clone(), socket))
+            .spawn(render::Renderer::handle_client(renderer.clone(), channel))
             .unwrap();
     }
 }
@@ -1,28 +1,32 @@
-use anyhow::Result;
-use futures::channel::mpsc::Sender;
-use futures::future::poll_fn;
-use host::matchmaker::Request;
-use host::socket::SocketManager;
+use crate::config::ModuleConfig;
+use crate::lifecycle::Event;
+use crate::matchmaker::{self, Request};
+use crate::metrics::ModuleMetrics;
+use crate::socket::SocketManager;
+use anyhow::{bail, format_err, Result};
+use futures::channel::mpsc::{Sender, UnboundedReceiver};
+use futures::future::{self, poll_fn, Either};
+use futures::{pin_mut, FutureExt, StreamExt};
+use futures_timer::Delay;
+use log::{debug, info};
+use protocols::idl::Imports;
 use protocols::*;
+use std::cell::Cell;
 use std::ffi::c_void;
 use std::fs::File;
-use std::io;
-use std::io::Read;
+use std::io::{self, Read};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
-use wasmer_runtime::{func, imports, instantiate, Array, Ctx, Func, Instance, Memory, WasmPtr};
+use std::time::{Duration, Instant};
+use wasmer_runtime::{compile, func, imports, Array, Ctx, Func, Instance, Module, WasmPtr};
 
 pub struct WasmModule {
     instance: Instance,
-}
-
-fn decode_string(
-    mem: &Memory,
-    arr: WasmPtr<u8, Array>,
-    len: u32,
-) -> Result<String, std::string::FromUtf8Error> {
-    let peer = arr.deref(mem, 0, len).unwrap();
-    String::from_utf8(peer.iter().map(|b| b.get()).collect())
+    metrics: Arc<ModuleMetrics>,
+    /// Version of the host interface the module was built against
+    abi_version: u32,
 }
 
 struct RuntimeSupply<'a, 'b> {
@@ -30,97 +34,454 @@ struct RuntimeSupply<'a, 'b> {
     sockman: &'a mut SocketManager,
 }
 
+/// Builds the import table from `protocols::host_imports!`. Each import enters the
+/// `RuntimeSupply` the module was called with, and reads or writes buffers in its memory. With
+/// `legacy`, handles are taken as 32 bits, as modules from before ABI version 2 pass them.
+macro_rules! wasm_imports {
+    (() $($(#[$attr:meta])* fn $name:ident ($($params:tt)*) $(-> $ret:ident)?;)*) => {
+        imports! {
+            "env" => {
+                $( stringify!($name) => wasm_import!((mem) $name [] [] ($($params)*) $(-> $ret)?), )*
+            },
+        }
+    };
+    ((legacy) $($(#[$attr:meta])* fn $name:ident ($($params:tt)*) $(-> $ret:ident)?;)*) => {
+        imports! {
+            "env" => {
+                $( stringify!($name) => wasm_import!((mem legacy) $name [] [] ($($params)*) $(-> $ret)?), )*
+            },
+        }
+    };
+}
+
+macro_rules! wasm_import {
+    (($mem:ident $($legacy:ident)?) $name:ident [$($params:tt)*] [$($args:tt)*] () -> Maybe) => {
+        func!(|ctx: &mut Ctx, $($params)*| {
+            #[allow(unused_variables)]
+            let ($mem, rt) = unsafe { ctx.memory_and_data_mut::<RuntimeSupply<'static, 'static>>(0) };
+            Maybe::encode(Imports::$name(rt, $($args)*))
+        })
+    };
+    (($mem:ident $($legacy:ident)?) $name:ident [$($params:tt)*] [$($args:tt)*] () -> Handle) => {
+        func!(|ctx: &mut Ctx, $($params)*| {
+            #[allow(unused_variables)]
+            let ($mem, rt) = unsafe { ctx.memory_and_data_mut::<RuntimeSupply<'static, 'static>>(0) };
+            Maybe::from_handle(Imports::$name(rt, $($args)*)).0
+        })
+    };
+    (($mem:ident $($legacy:ident)?) $name:ident [$($params:tt)*] [$($args:tt)*] ()) => {
+        func!(|ctx: &mut Ctx, $($params)*| {
+            #[allow(unused_variables)]
+            let ($mem, rt) = unsafe { ctx.memory_and_data_mut::<RuntimeSupply<'static, 'static>>(0) };
+            Imports::$name(rt, $($args)*)
+        })
+    };
+    (($mem:ident legacy) $name:ident [$($params:tt)*] [$($args:tt)*] (handle: $t:ty $(, $($rest:tt)*)?) $(-> $ret:ident)?) => {
+        wasm_import!(
+            ($mem legacy) $name
+            [$($params)* handle: u32,]
+            [$($args)* Handle::from(handle),]
+            ($($($rest)*)?) $(-> $ret)?
+        )
+    };
+    (($mem:ident $($legacy:ident)?) $name:ident [$($params:tt)*] [$($args:tt)*] ($p:ident: bytes $(, $($rest:tt)*)?) $(-> $ret:ident)?) => {
+        wasm_import!(
+            ($mem $($legacy)?) $name
+            [$($params)* $p: WasmPtr<u8, Array>, len: u32,]
+            [$($args)* &$p.deref($mem, 0, len).unwrap().iter().map(|b| b.get()).collect::<Vec<u8>>(),]
+            ($($($rest)*)?) $(-> $ret)?
+        )
+    };
+    (($mem:ident $($legacy:ident)?) $name:ident [$($params:tt)*] [$($args:tt)*] ($p:ident: bytes_mut $(, $($rest:tt)*)?) $(-> $ret:ident)?) => {
+        wasm_import!(
+            ($mem $($legacy)?) $name
+            [$($params)* $p: WasmPtr<u8, Array>, len: u32,]
+            [$($args)* $p.deref($mem, 0, len).unwrap(),]
+            ($($($rest)*)?) $(-> $ret)?
+        )
+    };
+    (($mem:ident $($legacy:ident)?) $name:ident [$($params:tt)*] [$($args:tt)*] ($p:ident: $t:ty $(, $($rest:tt)*)?) $(-> $ret:ident)?) => {
+        wasm_import!(
+            ($mem $($legacy)?) $name
+            [$($params)* $p: $t,]
+            [$($args)* $p,]
+            ($($($rest)*)?) $(-> $ret)?
+        )
+    };
+}
+
+impl Imports for RuntimeSupply<'_, '_> {
+    fn connect(&mut self, peer: &[u8], port: u16) -> Poll<io::Result<Handle>> {
+        self.sockman.connect_raw(peer, port)
+    }
+
+    fn listener_create(&mut self, port: u16) -> Poll<io::Result<Handle>> {
+        self.sockman.listener_create(port)
+    }
+
+    fn listen(&mut self, handle: Handle) -> Poll<io::Result<Handle>> {
+        self.sockman.listen(handle, self.cx)
+    }
+
+    fn close(&mut self, handle: Handle) {
+        self.sockman.close(handle)
+    }
+
+    fn read(&mut self, handle: Handle, buffer: &[Cell<u8>]) -> Poll<io::Result<u32>> {
+        self.sockman.read(handle, buffer, self.cx)
+    }
+
+    fn write(&mut self, handle: Handle, buffer: &[u8]) -> Poll<io::Result<u32>> {
+        self.sockman.write(handle, buffer, self.cx)
+    }
+
+    fn flush(&mut self, handle: Handle) -> Poll<io::Result<u32>> {
+        self.sockman.flush(handle, self.cx).map(|v| v.map(|_| 0))
+    }
+
+    fn shutdown(&mut self, handle: Handle) -> Poll<io::Result<u32>> {
+        self.sockman.shutdown(handle, self.cx).map(|v| v.map(|_| 0))
+    }
+
+    fn timer_create(&mut self, ms: u32) -> Poll<io::Result<Handle>> {
+        self.sockman.timer_create(Duration::from_millis(ms as u64))
+    }
+
+    fn timer_poll(&mut self, handle: Handle) -> Poll<io::Result<u32>> {
+        self.sockman.timer_poll(handle, self.cx)
+    }
+
+    fn random(&mut self, buffer: &[Cell<u8>]) -> Poll<io::Result<u32>> {
+        self.sockman.random(buffer)
+    }
+
+    fn socket_module(
+        &mut self,
+        handle: Handle,
+        peer: u32,
+        buffer: &[Cell<u8>],
+    ) -> Poll<io::Result<u32>> {
+        self.sockman.socket_module(handle, peer != 0, buffer)
+    }
+
+    fn socket_port(&mut self, handle: Handle, peer: u32) -> Poll<io::Result<u32>> {
+        self.sockman.socket_port(handle, peer != 0)
+    }
+
+    fn last_error_message(&mut self, handle: Handle, buffer: &[Cell<u8>]) -> Poll<io::Result<u32>> {
+        self.sockman.last_error_message(handle, buffer)
+    }
+
+    fn debug(&mut self, text: &[u8]) {
+        if let Ok(string) = std::str::from_utf8(text) {
+            info!("{}: {}", self.sockman.id(), string);
+        }
+    }
+}
+
 impl WasmModule {
-    pub fn from_path(path: impl AsRef<std::path::Path>) -> Result<Self> {
+    /// Compile the module at `path`. The result may be instantiated any number of times.
+    pub fn compile_path(path: impl AsRef<std::path::Path>) -> Result<Module> {
         let mut wasm = Vec::new();
         File::open(path)?.read_to_end(&mut wasm)?;
-        Self::new(&wasm)
+        compile(&wasm).map_err(|e| format_err!("Failed to compile module: {:?}", e))
     }
 
-    pub fn new(source: &[u8]) -> Result<Self> {
-        let import_object = imports! {
-            "env" => {
-                "write" => func!(|ctx: &mut Ctx, handle: Handle, buf: WasmPtr<u8, Array>, len: u32| {
-                    let (mem, rt) = unsafe { ctx.memory_and_data_mut::<RuntimeSupply<'static, 'static>>(0) };
-                    Maybe::encode(rt.sockman.write(handle, buf.deref(mem, 0, len).unwrap(), rt.cx))
-                }),
-
-                "flush" => func!(|ctx: &mut Ctx, handle: Handle| {
-                    let (_, rt) = unsafe { ctx.memory_and_data_mut::<RuntimeSupply<'static, 'static>>(0) };
-                    Maybe::encode(rt.sockman.flush(handle, rt.cx).map(|v| v.map(|_| 0)))
-                }),
-
-                "read" => func!(|ctx: &mut Ctx, handle: Handle, buf: WasmPtr<u8, Array>, len: u32| {
-                    let (mem, rt) = unsafe { ctx.memory_and_data_mut::<RuntimeSupply<'static, 'static>>(0) };
-                    Maybe::encode(rt.sockman.read(handle, buf.deref(mem, 0, len).unwrap(), rt.cx))
-                }),
-
-                "connect" => {
-                    func!(|ctx: &mut Ctx, peer: WasmPtr<u8, Array>, len: u32, port: u16| {
-                        let (mem, rt) = unsafe { ctx.memory_and_data_mut::<RuntimeSupply<'static, 'static>>(0) };
-                        if let Ok(peer) = decode_string(mem, peer, len) {
-                            Maybe::encode(rt.sockman.connect(&peer, port))
-                        } else {
-                            Maybe::encode(Poll::Ready(Err(io::Error::from(io::ErrorKind::InvalidData))))
-                        }
-                    })
-                },
-
-                "listener_create" => func!(|ctx: &mut Ctx, port: u16| {
-                    let (_, rt) = unsafe { ctx.memory_and_data_mut::<RuntimeSupply<'static, 'static>>(0) };
-                    Maybe::encode(rt.sockman.listener_create(port))
-                }),
-
-                "listen" => func!(|ctx: &mut Ctx, handle: Handle| {
-                    let (_, rt) = unsafe { ctx.memory_and_data_mut::<RuntimeSupply<'static, 'static>>(0) };
-                    Maybe::encode(rt.sockman.listen(handle, rt.cx))
-                }),
-
-                "close" => func!(|ctx: &mut Ctx, handle: Handle| {
-                    let (_, rt) = unsafe { ctx.memory_and_data_mut::<RuntimeSupply<'static, 'static>>(0) };
-                    rt.sockman.close(handle)
-                }),
-
-                "debug" => func!(|ctx: &mut Ctx, peer: WasmPtr<u8, Array>, len: u32| {
-                    if let Ok(string) = decode_string(ctx.memory(0), peer, len) {
-                        println!("Module debug: {}", string);
-                    }
-                }),
-            },
+    /// Create a new instance of a compiled module, with its own memory
+    pub fn new(module: &Module, metrics: Arc<ModuleMetrics>) -> Result<Self> {
+        // Imports which changed or are missing show up here, before the module can say which
+        // version it was built against. Modules from before version 2 take handles as 32 bits,
+        // so they link against imports of their own.
+        let import_object = protocols::host_imports!(wasm_imports);
+        let (instance, legacy) = match module.instantiate(&import_object) {
+            Ok(instance) => (instance, false),
+            Err(e) => {
+                let legacy_imports = protocols::host_imports!(wasm_imports, legacy);
+                let instance = module.instantiate(&legacy_imports).map_err(|_| {
+                    format_err!(
+                        "{}. The module may need a newer host (this one has ABI version {})",
+                        e,
+                        abi::VERSION
+                    )
+                })?;
+                (instance, true)
+            }
         };
 
-        let instance = instantiate(&source, &import_object).unwrap();
-
-        let main_func: Func = instance.func("main")?;
-        main_func.call().unwrap();
+        // Modules from before versioning don't export anything to check
+        let abi_version = match instance.func::<(), u32>("abi_version") {
+            Ok(func) => func
+                .call()
+                .map_err(|e| format_err!("abi_version failed: {:?}", e))?,
+            Err(_) => 0,
+        };
+        let features = match instance.func::<(), u64>("abi_features") {
+            Ok(func) => func
+                .call()
+                .map_err(|e| format_err!("abi_features failed: {:?}", e))?,
+            Err(_) => 0,
+        };
+        abi::check(abi_version, features).map_err(|e| format_err!("Incompatible module: {}", e))?;
+        if legacy && abi_version >= 2 {
+            bail!(
+                "Incompatible module: ABI version {} takes 64 bit handles, but it imports 32 bit ones",
+                abi_version
+            );
+        }
+        debug!(
+            "Module ABI version {}, using {:?}",
+            abi_version,
+            abi::feature_names(features)
+        );
 
-        Ok(Self { instance })
+        Ok(Self {
+            instance,
+            metrics,
+            abi_version,
+        })
     }
 
-    fn run(&mut self, sockman: &mut SocketManager, cx: &mut Context) {
+    /// Call into the module, with `sockman` available to imports for the duration of the call
+    fn enter<R>(
+        &mut self,
+        sockman: &mut SocketManager,
+        cx: &mut Context,
+        f: impl FnOnce(&Instance) -> Result<R>,
+    ) -> Result<R> {
         let runtime_supply = RuntimeSupply { sockman, cx };
         self.instance.context_mut().data = &runtime_supply as *const _ as *mut c_void;
+        let ret = f(&self.instance);
+        self.instance.context_mut().data = std::ptr::null_mut();
+        ret
+    }
 
-        let wake_func: Func<u32, ()> = self.instance.func("wake").unwrap();
+    /// Wake and run the module's tasks. Returns true once the module has no tasks left.
+    fn run(
+        &mut self,
+        sockman: &mut SocketManager,
+        cx: &mut Context,
+        config: &ModuleConfig,
+    ) -> Result<bool> {
+        let wakes = sockman.wakes(cx);
+        self.metrics
+            .wakes
+            .fetch_add(wakes.len() as u64, Ordering::Relaxed);
+        let metrics = self.metrics.clone();
+        let abi_version = self.abi_version;
+        let start = Instant::now();
+        let finished = self.enter(sockman, cx, |instance| {
+            // Handles given to modules from before version 2 fit in 32 bits
+            if abi_version >= 2 {
+                let wake_func: Func<(Handle, u32), ()> = instance.func("wake")?;
+                for (handle, interest) in wakes {
+                    wake_func
+                        .call(handle, interest as u32)
+                        .map_err(|e| trap(&metrics, "wake", e))?;
+                }
+            } else if abi_version >= 1 {
+                let wake_func: Func<(u32, u32), ()> = instance.func("wake")?;
+                for (handle, interest) in wakes {
+                    wake_func
+                        .call(handle as u32, interest as u32)
+                        .map_err(|e| trap(&metrics, "wake", e))?;
+                }
+            } else {
+                // Older modules keep one waker per handle
+                let wake_func: Func<u32, ()> = instance.func("wake")?;
+                let mut handles: Vec<Handle> =
+                    wakes.into_iter().map(|(handle, _)| handle).collect();
+                handles.dedup();
+                for handle in handles {
+                    wake_func
+                        .call(handle as u32)
+                        .map_err(|e| trap(&metrics, "wake", e))?;
+                }
+            }
 
-        for handle in sockman.wakes(cx) {
-            wake_func.call(handle).unwrap();
+            // Modules from before versioning don't report how many tasks they have left
+            if abi_version == 0 {
+                let poll_func: Func = instance.func("run_tasks")?;
+                poll_func
+                    .call()
+                    .map(|_| false)
+                    .map_err(|e| trap(&metrics, "task", e))
+            } else {
+                let poll_func: Func<(), u32> = instance.func("run_tasks")?;
+                poll_func
+                    .call()
+                    .map(|remaining| remaining == 0)
+                    .map_err(|e| trap(&metrics, "task", e))
+            }
+        });
+        self.metrics
+            .run_time_ns
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        let pages = self.instance.context().memory(0).size().0;
+        self.metrics
+            .memory_pages
+            .store(u64::from(pages), Ordering::Relaxed);
+        let finished = finished?;
+
+        // Only checked once the call has returned; nothing interrupts a module mid-call
+        if let Some(limit) = config.run_time_check_ms {
+            let elapsed = start.elapsed();
+            if elapsed > Duration::from_millis(limit) {
+                bail!("Call ran for too long ({:?} > {}ms)", elapsed, limit);
+            }
+        }
+        if let Some(limit) = config.memory_check_pages {
+            if u64::from(pages) > limit {
+                bail!("Memory grew too large ({} > {} pages)", pages, limit);
+            }
         }
 
-        let poll_func: Func = self.instance.func("run_tasks").unwrap();
-        poll_func.call().expect("Wasm task failure");
+        Ok(finished)
+    }
+
+    /// Call one of the optional lifecycle exports with a string argument, if the module has it.
+    fn call_hook(
+        &mut self,
+        name: &str,
+        arg: &str,
+        sockman: &mut SocketManager,
+        cx: &mut Context,
+    ) -> Result<()> {
+        let metrics = self.metrics.clone();
+        self.enter(sockman, cx, |instance| {
+            let hook: Func<(u32, u32), ()> = match instance.func(name) {
+                Ok(hook) => hook,
+                Err(_) => return Ok(()),
+            };
+            let (ptr, len) = pass_string(instance, arg, &metrics)?;
+            hook.call(ptr, len).map_err(|e| trap(&metrics, name, e))
+        })
     }
 
-    pub async fn task(mut self, id: ModuleId, matchmaker: Sender<Request>) {
-        let mut sockman = SocketManager::new(id.clone(), matchmaker);
-        loop {
-            poll_fn(|cx| {
-                //eprintln!("\n************ {} ************", id);
-                self.run(&mut sockman, cx);
-                //eprintln!("\n************ END {} ************", id);
-                Poll::<()>::Pending
-            })
-            .await;
+    /// Tell the module it has `grace` to finish up, if it has an `on_shutdown` export.
+    fn on_shutdown(
+        &mut self,
+        grace: Duration,
+        sockman: &mut SocketManager,
+        cx: &mut Context,
+    ) -> Result<()> {
+        let metrics = self.metrics.clone();
+        self.enter(sockman, cx, |instance| {
+            if let Ok(hook) = instance.func::<u32, ()>("on_shutdown") {
+                hook.call(grace.as_millis() as u32)
+                    .map_err(|e| trap(&metrics, "on_shutdown", e))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Run the module. Calls `init` straight away, then waits for the module's dependencies to
+    /// start listening before calling `main` and running tasks. `events` should be subscribed
+    /// before the task is spawned, so that no lifecycle event is missed.
+    pub async fn task(
+        mut self,
+        id: ModuleId,
+        config: ModuleConfig,
+        mut matchmaker: Sender<Request>,
+        mut events: UnboundedReceiver<Event>,
+    ) -> Result<()> {
+        let mut sockman = SocketManager::new(id.clone(), matchmaker.clone(), self.metrics.clone())
+            .with_loopback_config(config.loopback_config());
+        if self.abi_version < 2 {
+            sockman = sockman.with_32_bit_handles();
+        }
+        if let Some(seed) = config.seed {
+            sockman = sockman.with_seed(seed);
         }
+
+        let init_config = toml::to_string(&config.config)?;
+        poll_fn(|cx| Poll::Ready(self.call_hook("init", &init_config, &mut sockman, cx))).await?;
+
+        let deps_ready = async {
+            for dep in &config.depends {
+                matchmaker::wait_for_listener(dep.module.clone(), dep.port, &mut matchmaker)
+                    .await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+        // Don't start at all if the kernel shuts down while we're waiting. Peers which stop in
+        // the meantime are reported once the wait is over.
+        let mut peers_down = Vec::new();
+        let mut shutdowns = events.by_ref().filter(|event| {
+            if let Event::PeerDown(peer) = event {
+                peers_down.push(peer.clone());
+            }
+            future::ready(matches!(event, Event::Shutdown(_)))
+        });
+        pin_mut!(deps_ready);
+        match future::select(deps_ready, shutdowns.next()).await {
+            Either::Left((ready, _)) => ready?,
+            Either::Right(_) => return Ok(()),
+        }
+        drop(shutdowns);
+        for peer in peers_down.iter().filter(|peer| **peer != id) {
+            poll_fn(|cx| Poll::Ready(self.call_hook("on_peer_down", peer, &mut sockman, cx)))
+                .await?;
+        }
+
+        let metrics = self.metrics.clone();
+        poll_fn(|cx| {
+            Poll::Ready(self.enter(&mut sockman, cx, |instance| {
+                let main_func: Func = instance.func("main")?;
+                main_func.call().map_err(|e| trap(&metrics, "main", e))
+            }))
+        })
+        .await?;
+
+        // Set once a shutdown is requested; the module runs until it closes all of its handles or
+        // the deadline passes.
+        let mut deadline: Option<Delay> = None;
+
+        poll_fn(|cx| {
+            while let Poll::Ready(Some(event)) = events.poll_next_unpin(cx) {
+                match event {
+                    Event::PeerDown(peer) if peer != id => {
+                        self.call_hook("on_peer_down", &peer, &mut sockman, cx)?
+                    }
+                    Event::PeerDown(_) => (),
+                    Event::Shutdown(grace) => {
+                        self.on_shutdown(grace, &mut sockman, cx)?;
+                        deadline = Some(Delay::new(grace));
+                    }
+                }
+            }
+            //eprintln!("\n************ {} ************", id);
+            if self.run(&mut sockman, cx, &config)? {
+                return Poll::Ready(Ok(()));
+            }
+            //eprintln!("\n************ END {} ************", id);
+            if let Some(deadline) = &mut deadline {
+                if sockman.is_empty() || deadline.poll_unpin(cx).is_ready() {
+                    return Poll::Ready(Ok(()));
+                }
+            }
+            Poll::Pending
+        })
+        .await
     }
 }
+
+/// Copy `s` into the instance's memory through its `alloc` export. Ownership of the buffer passes
+/// to the module.
+fn pass_string(instance: &Instance, s: &str, metrics: &ModuleMetrics) -> Result<(u32, u32)> {
+    let alloc: Func<u32, u32> = instance.func("alloc")?;
+    let len = s.len() as u32;
+    let ptr = alloc.call(len).map_err(|e| trap(metrics, "alloc", e))?;
+    let buf = WasmPtr::<u8, Array>::new(ptr)
+        .deref(instance.context().memory(0), 0, len)
+        .ok_or_else(|| format_err!("Module returned an invalid allocation"))?;
+    for (cell, byte) in buf.iter().zip(s.bytes()) {
+        cell.set(byte);
+    }
+    Ok((ptr, len))
+}
+
+/// Describe a failed call into the module, counting it as a trap
+fn trap(metrics: &ModuleMetrics, call: &str, error: impl std::fmt::Debug) -> anyhow::Error {
+    metrics.traps.fetch_add(1, Ordering::Relaxed);
+    format_err!("Wasm {} failure: {:?}", call, error)
+}
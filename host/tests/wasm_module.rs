@@ -0,0 +1,86 @@
+use futures::channel::mpsc::{channel, unbounded};
+use futures::executor::{block_on, LocalPool};
+use futures::task::SpawnExt;
+use futures::FutureExt;
+use host::config::{Dependency, ModuleConfig};
+use host::lifecycle::Event;
+use host::matchmaker::MatchMaker;
+use host::metrics::ModuleMetrics;
+use host::socket::SocketManager;
+use host::wasm_module::WasmModule;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn instantiate(wat: &str) -> WasmModule {
+    let wasm = wat::parse_str(wat).unwrap();
+    let module = wasmer_runtime::compile(&wasm).unwrap();
+    WasmModule::new(&module, Arc::new(ModuleMetrics::default())).unwrap()
+}
+
+/// A module from before versioning: no `abi_version` export, a single-argument `wake`, and a
+/// `run_tasks` which returns nothing
+const UNVERSIONED: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "main"))
+  (func (export "wake") (param i32))
+  (func (export "run_tasks")))
+"#;
+
+#[test]
+fn unversioned_modules_run() {
+    let instance = instantiate(UNVERSIONED);
+
+    // Runs until the shutdown, as it can't say when it has finished by itself
+    let (matchmaker, _) = channel(1);
+    let (lifecycle, events) = unbounded();
+    lifecycle
+        .unbounded_send(Event::Shutdown(Duration::from_millis(10)))
+        .unwrap();
+    block_on(instance.task("old".into(), ModuleConfig::default(), matchmaker, events)).unwrap();
+}
+
+/// Traps when its tasks are run, unless it has been told about a peer going down
+const WATCHES_PEERS: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (global $peer_down (mut i32) (i32.const 0))
+  (func (export "abi_version") (result i32) (i32.const 1))
+  (func (export "alloc") (param i32) (result i32) (i32.const 16))
+  (func (export "on_peer_down") (param i32 i32) (global.set $peer_down (i32.const 1)))
+  (func (export "main"))
+  (func (export "wake") (param i32 i32))
+  (func (export "run_tasks") (result i32)
+    (if (i32.eqz (global.get $peer_down)) (then unreachable))
+    (i32.const 0)))
+"#;
+
+#[test]
+fn peers_down_while_waiting_for_dependencies_are_reported() {
+    let mut pool = LocalPool::new();
+    let (matchmaker, conn) = MatchMaker::new();
+    pool.spawner().spawn(matchmaker.task()).unwrap();
+
+    let config = ModuleConfig {
+        depends: vec![Dependency {
+            module: "dep".into(),
+            port: 5,
+        }],
+        ..Default::default()
+    };
+    let (lifecycle, events) = unbounded();
+    let (task, finished) = instantiate(WATCHES_PEERS)
+        .task("watcher".into(), config, conn.clone(), events)
+        .remote_handle();
+    pool.spawner().spawn(task).unwrap();
+
+    // Seen while the module is still waiting for its dependency
+    lifecycle
+        .unbounded_send(Event::PeerDown("gone".into()))
+        .unwrap();
+    pool.run_until_stalled();
+
+    let mut dep = SocketManager::new("dep".into(), conn, Arc::new(ModuleMetrics::default()));
+    assert!(dep.listener_create(5).is_ready());
+    pool.run_until(finished).unwrap();
+}
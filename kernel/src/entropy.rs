@@ -0,0 +1,44 @@
+use rand::rngs::{OsRng, StdRng};
+use rand::{RngCore, SeedableRng};
+use std::io;
+
+/// Source of the randomness handed to a module
+pub struct Entropy {
+    rng: Box<dyn RngCore + Send>,
+}
+
+impl Entropy {
+    /// Randomness from the operating system
+    pub fn os() -> Self {
+        Self {
+            rng: Box::new(OsRng),
+        }
+    }
+
+    /// Reproducible randomness. Each `stream` gets different bytes from the same seed.
+    pub fn seeded(seed: u64, stream: &str) -> Self {
+        Self {
+            rng: Box::new(StdRng::seed_from_u64(seed ^ fnv1a(stream.as_bytes()))),
+        }
+    }
+
+    /// Fill `buf` with random bytes
+    pub fn fill(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.rng
+            .try_fill_bytes(buf)
+            .map_err(|_| io::Error::from(io::ErrorKind::Other))
+    }
+}
+
+impl Default for Entropy {
+    fn default() -> Self {
+        Self::os()
+    }
+}
+
+/// A hash which is the same on every platform and Rust version
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
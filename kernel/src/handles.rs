@@ -0,0 +1,129 @@
+//! Allocation of generational handles. Closed handles give their slot back to be reused under the
+//! next generation, so a module holding on to a closed handle can't reach whatever replaced it.
+
+use protocols::{Handle, GENERATION_BITS};
+use std::collections::HashSet;
+
+const SLOT_BITS: u32 = 32;
+const GENERATION_MASK: u32 = (1 << GENERATION_BITS) - 1;
+
+/// What a handle refers to, as far as the allocator knows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Live,
+    /// The handle was closed, and its slot may since have been reused
+    Stale,
+    /// The handle was never handed out
+    Unknown,
+}
+
+struct Slot {
+    generation: u32,
+    live: bool,
+}
+
+/// Handles which fit in 32 bits, counting up and never reused. Only the open ones are stored.
+#[derive(Default)]
+struct Narrow {
+    next: u32,
+    live: HashSet<u32>,
+}
+
+#[derive(Default)]
+pub struct Handles {
+    slots: Vec<Slot>,
+    /// Slots of closed handles, waiting to be reused
+    free: Vec<u32>,
+    /// Used instead of the slots for 32 bit handles
+    narrow: Option<Narrow>,
+}
+
+impl Handles {
+    /// Handles for modules from before ABI version 2, which pass them as 32 bits. They count up
+    /// from 0 as handles always used to, so closed ones can still be told apart.
+    pub fn narrow() -> Self {
+        Self {
+            narrow: Some(Narrow::default()),
+            ..Self::default()
+        }
+    }
+
+    /// Hand out a handle, reusing a free slot if there is one
+    pub fn create(&mut self) -> Handle {
+        if let Some(narrow) = &mut self.narrow {
+            let handle = narrow.next;
+            narrow.next += 1;
+            narrow.live.insert(handle);
+            return Handle::from(handle);
+        }
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                let entry = &mut self.slots[slot as usize];
+                entry.generation = (entry.generation + 1) & GENERATION_MASK;
+                entry.live = true;
+                slot
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    live: true,
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        join(slot, self.slots[slot as usize].generation)
+    }
+
+    /// Give back a live handle's slot. Returns false, and leaves the slot alone, if the handle
+    /// isn't live.
+    pub fn release(&mut self, handle: Handle) -> bool {
+        if self.status(handle) != Status::Live {
+            return false;
+        }
+        let (slot, _) = split(handle);
+        match &mut self.narrow {
+            Some(narrow) => {
+                narrow.live.remove(&slot);
+            }
+            None => {
+                self.slots[slot as usize].live = false;
+                self.free.push(slot);
+            }
+        }
+        true
+    }
+
+    pub fn status(&self, handle: Handle) -> Status {
+        let (slot, generation) = split(handle);
+        if let Some(narrow) = &self.narrow {
+            return if generation != 0 || slot >= narrow.next {
+                Status::Unknown
+            } else if narrow.live.contains(&slot) {
+                Status::Live
+            } else {
+                Status::Stale
+            };
+        }
+        let entry = match self.slots.get(slot as usize) {
+            Some(entry) => entry,
+            None => return Status::Unknown,
+        };
+        if generation == entry.generation && entry.live {
+            Status::Live
+        } else if generation <= entry.generation {
+            Status::Stale
+        } else {
+            // Also the case for handles from before a slot's generation wrapped, which are still
+            // turned away
+            Status::Unknown
+        }
+    }
+}
+
+fn join(slot: u32, generation: u32) -> Handle {
+    Handle::from(generation) << SLOT_BITS | Handle::from(slot)
+}
+
+fn split(handle: Handle) -> (u32, u32) {
+    (handle as u32, (handle >> SLOT_BITS) as u32)
+}
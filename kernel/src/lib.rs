@@ -0,0 +1,5 @@
+pub mod entropy;
+pub mod handles;
+pub mod matchmaker;
+pub mod metrics;
+pub mod socket;
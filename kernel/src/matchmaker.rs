@@ -0,0 +1,405 @@
+use futures::channel::mpsc::{channel, Receiver, SendError, Sender};
+use futures::future;
+use futures::sink::SinkExt;
+use futures::stream::{Stream, StreamExt};
+use loopback::typed::{self, Channel, TypedEnd};
+use loopback::{Connection, Faults, FaultyLoopback, Loopback, LoopbackConfig};
+use protocols::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::Any;
+use std::collections::HashMap;
+
+pub type MatchMakerConnection = Sender<Request>;
+pub type ConnSender = Sender<(Matched, Endpoints)>;
+
+/// First port handed to the connecting end of a connection
+const EPHEMERAL_PORTS: Port = 49152;
+
+/// What the match maker hands to each side of a new connection
+pub enum Matched {
+    /// A byte stream
+    Bytes(Connection),
+    /// A typed channel. The listener receives the end offered by the connector, and the connector
+    /// receives `()` to confirm that the offer was taken.
+    Typed(Box<dyn Any + Send>),
+}
+
+/// Who is at each end of a new connection. Filled in by the match maker from its requests, so a
+/// module can't claim to be someone else.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoints {
+    pub local: Addr,
+    pub peer: Addr,
+}
+
+impl Matched {
+    /// The byte stream, for those who never ask for typed channels
+    pub fn bytes(self) -> Option<Connection> {
+        match self {
+            Matched::Bytes(conn) => Some(conn),
+            Matched::Typed(_) => None,
+        }
+    }
+}
+
+/// Connect to a module via MatchMaker
+pub async fn connect(
+    id: impl Into<ModuleId>,
+    port: Port,
+    matchmaker: &mut MatchMakerConnection,
+) -> Result<Option<Connection>, SendError> {
+    connect_from(ModuleId::new(), id, port, matchmaker).await
+}
+
+/// Connect to a module via MatchMaker on behalf of the module `from`, which the listener sees as
+/// the peer
+pub async fn connect_from(
+    from: impl Into<ModuleId>,
+    id: impl Into<ModuleId>,
+    port: Port,
+    matchmaker: &mut MatchMakerConnection,
+) -> Result<Option<Connection>, SendError> {
+    let (dest_socket, mut socket) = channel(MATCHMAKER_MAX_REQ);
+    matchmaker
+        .send(Request {
+            dest_socket,
+            id: id.into(),
+            from: from.into(),
+            port,
+            conn_type: ConnType::Connector,
+            config: Default::default(),
+            typed: None,
+        })
+        .await?;
+    Ok(socket.next().await.and_then(|(matched, _)| matched.bytes()))
+}
+
+/// Connect to a native service with a typed channel. If the listener only accepts byte streams,
+/// the channel falls back to bincode.
+pub async fn connect_typed<S, R>(
+    id: impl Into<ModuleId>,
+    port: Port,
+    matchmaker: &mut MatchMakerConnection,
+) -> Result<Option<Channel<S, R>>, SendError>
+where
+    S: Send + 'static,
+    R: Send + 'static,
+{
+    let (ours, theirs) = typed::pair::<S, R>();
+    let (dest_socket, mut socket) = channel(MATCHMAKER_MAX_REQ);
+    matchmaker
+        .send(Request {
+            dest_socket,
+            id: id.into(),
+            from: ModuleId::new(),
+            port,
+            conn_type: ConnType::Connector,
+            config: Default::default(),
+            typed: Some(Box::new(theirs)),
+        })
+        .await?;
+    Ok(socket.next().await.map(|(matched, _)| match matched {
+        Matched::Bytes(conn) => Channel::serialized(conn),
+        Matched::Typed(_) => Channel::typed(ours),
+    }))
+}
+
+/// Create a new socket listener via MatchMaker
+pub async fn create_listener(
+    id: impl Into<ModuleId>,
+    port: Port,
+    matchmaker: &mut MatchMakerConnection,
+) -> Result<impl Stream<Item = Connection>, SendError> {
+    create_listener_with(id, port, Default::default(), matchmaker).await
+}
+
+/// Create a new socket listener via MatchMaker, whose connections use the given settings
+pub async fn create_listener_with(
+    id: impl Into<ModuleId>,
+    port: Port,
+    config: LoopbackConfig,
+    matchmaker: &mut MatchMakerConnection,
+) -> Result<impl Stream<Item = Connection>, SendError> {
+    let socket = listen(id.into(), port, config, false, matchmaker).await?;
+    Ok(socket.filter_map(|(matched, _)| future::ready(matched.bytes())))
+}
+
+/// Create a listener for a native service. Native peers connecting with `connect_typed` hand
+/// values over directly, while wasm modules are served with bincode over a byte stream. Typed
+/// connections offering the wrong types are dropped.
+pub async fn create_typed_listener<S, R>(
+    id: impl Into<ModuleId>,
+    port: Port,
+    config: LoopbackConfig,
+    matchmaker: &mut MatchMakerConnection,
+) -> Result<impl Stream<Item = Channel<S, R>>, SendError>
+where
+    S: Serialize + Send + 'static,
+    R: DeserializeOwned + Send + 'static,
+{
+    let socket = listen(id.into(), port, config, true, matchmaker).await?;
+    Ok(socket.filter_map(|(matched, _)| {
+        future::ready(match matched {
+            Matched::Bytes(conn) => Some(Channel::serialized(conn)),
+            Matched::Typed(end) => end
+                .downcast::<TypedEnd<S, R>>()
+                .ok()
+                .map(|end| Channel::typed(*end)),
+        })
+    }))
+}
+
+async fn listen(
+    id: ModuleId,
+    port: Port,
+    config: LoopbackConfig,
+    typed: bool,
+    matchmaker: &mut MatchMakerConnection,
+) -> Result<Receiver<(Matched, Endpoints)>, SendError> {
+    let (dest_socket, socket) = channel(MATCHMAKER_MAX_REQ);
+    matchmaker
+        .send(Request {
+            dest_socket,
+            from: id.clone(),
+            id,
+            port,
+            conn_type: ConnType::Listener,
+            config,
+            typed: if typed { Some(Box::new(())) } else { None },
+        })
+        .await?;
+    Ok(socket)
+}
+
+/// Wait until a listener exists at the given address, without connecting to it
+pub async fn wait_for_listener(
+    id: impl Into<ModuleId>,
+    port: Port,
+    matchmaker: &mut MatchMakerConnection,
+) -> Result<(), SendError> {
+    let (dest_socket, mut socket) = channel(MATCHMAKER_MAX_REQ);
+    matchmaker
+        .send(Request {
+            dest_socket,
+            id: id.into(),
+            from: ModuleId::new(),
+            port,
+            conn_type: ConnType::Waiter,
+            config: Default::default(),
+            typed: None,
+        })
+        .await?;
+    // The match maker hangs up on us once the listener is available
+    let _ = socket.next().await;
+    Ok(())
+}
+
+/// A request to the match maker
+pub struct Request {
+    /// Connector: Destination host; Listener: Host
+    pub id: ModuleId,
+    /// Module making the request, or empty for the host itself
+    pub from: ModuleId,
+    /// Broadcast or destination port
+    pub port: Port,
+    /// Connection type (listener, connector)
+    pub conn_type: ConnType,
+    /// Channel on which to receive connections
+    pub dest_socket: ConnSender,
+    /// Listener: settings for connections made to it; otherwise unused
+    pub config: LoopbackConfig,
+    /// Connector: the listener's end of a typed channel, used if the listener accepts it.
+    /// Listener: `Some` if typed channels are accepted.
+    pub typed: Option<Box<dyn Any + Send>>,
+}
+
+/// Connection type (listener, connector, waiter)
+#[derive(Debug)]
+pub enum ConnType {
+    Connector,
+    Listener,
+    /// Never receives a connection; `dest_socket` is dropped once a listener is available
+    Waiter,
+}
+
+struct Listener {
+    sender: ConnSender,
+    config: LoopbackConfig,
+    typed: bool,
+}
+
+struct Connector {
+    from: ModuleId,
+    sender: ConnSender,
+    typed: Option<Box<dyn Any + Send>>,
+}
+
+/// Connection facilitator
+pub struct MatchMaker {
+    receiver: Receiver<Request>,
+    active_connections: HashMap<(ModuleId, Port), Vec<Connector>>,
+    listeners: HashMap<(ModuleId, Port), Listener>,
+    waiters: HashMap<(ModuleId, Port), Vec<ConnSender>>,
+    faults: HashMap<ModuleId, Faults>,
+    /// Number of connections made so far
+    connections: u64,
+}
+
+/// Match maker channel message limit
+pub const MATCHMAKER_MAX_REQ: usize = 32;
+
+impl MatchMaker {
+    /// Create a new match maker, returning a channel which listens to requests
+    pub fn new() -> (Self, MatchMakerConnection) {
+        let (sender, receiver) = channel(MATCHMAKER_MAX_REQ);
+        let instance = Self {
+            receiver,
+            active_connections: Default::default(),
+            listeners: Default::default(),
+            waiters: Default::default(),
+            faults: Default::default(),
+            connections: 0,
+        };
+        (instance, sender)
+    }
+
+    /// Simulate bad conditions on every connection the given module makes or accepts
+    pub fn inject_faults(&mut self, module: ModuleId, faults: Faults) {
+        self.faults.insert(module, faults);
+    }
+
+    /// Connect a listener and a connector, returning what each should receive
+    fn pair(
+        &mut self,
+        addr: &(ModuleId, Port),
+        listener: &Listener,
+        connector: &mut Connector,
+    ) -> ((Matched, Endpoints), (Matched, Endpoints)) {
+        let stream = self.connections;
+        self.connections += 1;
+        let listener_end = Addr {
+            module: addr.0.clone(),
+            port: addr.1,
+        };
+        let connector_end = Addr {
+            module: connector.from.clone(),
+            port: EPHEMERAL_PORTS + (stream % (Port::MAX - EPHEMERAL_PORTS + 1) as u64) as Port,
+        };
+        let endpoints = (
+            Endpoints {
+                local: listener_end.clone(),
+                peer: connector_end.clone(),
+            },
+            Endpoints {
+                local: connector_end,
+                peer: listener_end,
+            },
+        );
+
+        if listener.typed {
+            if let Some(end) = connector.typed.take() {
+                return (
+                    (Matched::Typed(end), endpoints.0),
+                    (Matched::Typed(Box::new(())), endpoints.1),
+                );
+            }
+        }
+
+        let (a, b) = Loopback::pair_with(listener.config);
+        (
+            (Matched::Bytes(self.wrap(&addr.0, a, stream)), endpoints.0),
+            (
+                Matched::Bytes(self.wrap(&connector.from, b, stream)),
+                endpoints.1,
+            ),
+        )
+    }
+
+    fn wrap(&self, module: &ModuleId, socket: Loopback, stream: u64) -> Connection {
+        match self.faults.get(module) {
+            Some(faults) => Box::new(FaultyLoopback::new(socket, faults.clone(), stream)),
+            None => Box::new(socket),
+        }
+    }
+
+    /// The match maker loop, handles new connections through the MatchMakerConnection channel
+    /// returned on creation. Returns once that channel is closed.
+    pub async fn task(mut self) {
+        while let Some(msg) = self.receiver.next().await {
+            let addr = (msg.id, msg.port);
+            match msg.conn_type {
+                ConnType::Listener => {
+                    let listener = Listener {
+                        sender: msg.dest_socket,
+                        config: msg.config,
+                        typed: msg.typed.is_some(),
+                    };
+                    self.new_listener(addr, listener).await
+                }
+                ConnType::Connector => {
+                    let connector = Connector {
+                        from: msg.from,
+                        sender: msg.dest_socket,
+                        typed: msg.typed,
+                    };
+                    self.new_connector(addr, connector).await
+                }
+                ConnType::Waiter => self.new_waiter(addr, msg.dest_socket),
+            }
+        }
+    }
+
+    async fn new_connector(&mut self, addr: (ModuleId, Port), mut connector: Connector) {
+        // Atempt to connect the socket immediately
+        if let Some(mut listener) = self.listeners.remove(&addr) {
+            let (a, b) = self.pair(&addr, &listener, &mut connector);
+            if listener.sender.send(a).await.is_ok() {
+                self.listeners.insert(addr, listener);
+                // Note that we don't care about the return value, because if it failed to send
+                // then the other side will notice when it is unable to send or receive
+                let _ = connector.sender.send(b).await;
+
+                // Don't add this connector to our collection, as connectors are one-shot.
+                return;
+            }
+            // Connection is hung up, never attempt to contact it again. A typed offer has been
+            // used up, so the connector will have to make do with a byte stream.
+        }
+
+        // Slate this connector for connection as soon as the listener its looking for becomes
+        // available.
+        self.active_connections
+            .entry(addr)
+            .or_insert(vec![])
+            .push(connector)
+    }
+
+    async fn new_listener(&mut self, addr: (ModuleId, Port), mut listener: Listener) {
+        // If there's a connector list for the address of the connecting listener, try to create a
+        // connector for each entry.
+        let mut connector_list = self.active_connections.remove(&addr).unwrap_or_default();
+        while let Some(mut connector) = connector_list.pop() {
+            let (a, b) = self.pair(&addr, &listener, &mut connector);
+
+            if listener.sender.send(a).await.is_err() {
+                connector_list.push(connector);
+                self.active_connections.insert(addr, connector_list);
+                // Abort without adding the listener to the `listeners` collection.
+                return;
+            }
+
+            let _ = connector.sender.send(b).await;
+        }
+
+        // Release anyone waiting for this listener to show up
+        self.waiters.remove(&addr);
+        self.listeners.insert(addr, listener);
+    }
+
+    fn new_waiter(&mut self, addr: (ModuleId, Port), waiter: ConnSender) {
+        if !self.listeners.contains_key(&addr) {
+            self.waiters.entry(addr).or_insert(vec![]).push(waiter);
+        }
+    }
+}
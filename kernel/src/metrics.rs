@@ -0,0 +1,186 @@
+use protocols::ModuleId;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Runtime counters for a single module, updated by its task and socket manager
+#[derive(Debug, Default)]
+pub struct ModuleMetrics {
+    pub run_time_ns: AtomicU64,
+    pub wakes: AtomicU64,
+    pub bytes_read: AtomicU64,
+    pub bytes_written: AtomicU64,
+    pub messages_read: AtomicU64,
+    pub messages_written: AtomicU64,
+    pub open_handles: AtomicU64,
+    pub memory_pages: AtomicU64,
+    pub traps: AtomicU64,
+}
+
+/// A point-in-time copy of a module's counters
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Time spent in the module's `run_tasks`
+    pub run_time: Duration,
+    pub wakes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub messages_read: u64,
+    pub messages_written: u64,
+    pub open_handles: u64,
+    pub memory_pages: u64,
+    pub traps: u64,
+}
+
+impl ModuleMetrics {
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            run_time: Duration::from_nanos(get(&self.run_time_ns)),
+            wakes: get(&self.wakes),
+            bytes_read: get(&self.bytes_read),
+            bytes_written: get(&self.bytes_written),
+            messages_read: get(&self.messages_read),
+            messages_written: get(&self.messages_written),
+            open_handles: get(&self.open_handles),
+            memory_pages: get(&self.memory_pages),
+            traps: get(&self.traps),
+        }
+    }
+}
+
+/// Registry of the metrics of every module in the kernel
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    modules: Arc<Mutex<BTreeMap<ModuleId, Arc<ModuleMetrics>>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create counters for a module. Registering the same id again resets its counters.
+    pub fn register(&self, id: ModuleId) -> Arc<ModuleMetrics> {
+        let metrics = Arc::new(ModuleMetrics::default());
+        self.modules.lock().unwrap().insert(id, metrics.clone());
+        metrics
+    }
+
+    /// Query the current counters of every module
+    pub fn snapshot(&self) -> Vec<(ModuleId, MetricsSnapshot)> {
+        self.modules
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, metrics)| (id.clone(), metrics.snapshot()))
+            .collect()
+    }
+
+    /// Render the current counters in the Prometheus text exposition format
+    pub fn prometheus(&self) -> String {
+        type Family = (
+            &'static str,
+            &'static str,
+            &'static str,
+            fn(&MetricsSnapshot) -> f64,
+        );
+        const FAMILIES: [Family; 9] = [
+            (
+                "kernel_module_run_seconds_total",
+                "counter",
+                "Time spent running module tasks",
+                |m| m.run_time.as_secs_f64(),
+            ),
+            (
+                "kernel_module_wakes_total",
+                "counter",
+                "Handles woken in the module",
+                |m| m.wakes as f64,
+            ),
+            (
+                "kernel_module_read_bytes_total",
+                "counter",
+                "Bytes read from sockets",
+                |m| m.bytes_read as f64,
+            ),
+            (
+                "kernel_module_written_bytes_total",
+                "counter",
+                "Bytes written to sockets",
+                |m| m.bytes_written as f64,
+            ),
+            (
+                "kernel_module_read_messages_total",
+                "counter",
+                "Successful socket reads",
+                |m| m.messages_read as f64,
+            ),
+            (
+                "kernel_module_written_messages_total",
+                "counter",
+                "Successful socket writes",
+                |m| m.messages_written as f64,
+            ),
+            (
+                "kernel_module_open_handles",
+                "gauge",
+                "Open sockets, listeners and connectors",
+                |m| m.open_handles as f64,
+            ),
+            (
+                "kernel_module_memory_pages",
+                "gauge",
+                "Module memory size in 64KiB pages",
+                |m| m.memory_pages as f64,
+            ),
+            (
+                "kernel_module_traps_total",
+                "counter",
+                "Calls into the module which trapped",
+                |m| m.traps as f64,
+            ),
+        ];
+
+        let snapshot = self.snapshot();
+        let mut out = String::new();
+        for (name, kind, help, value) in FAMILIES.iter() {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (id, metrics) in &snapshot {
+                let id = id.replace('\\', "\\\\").replace('"', "\\\"");
+                let _ = writeln!(out, "{}{{module=\"{}\"}} {}", name, id, value(metrics));
+            }
+        }
+        out
+    }
+
+    /// Serve the Prometheus text format over HTTP on a localhost port, from a new thread
+    pub fn serve(&self, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let metrics = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                // Every request gets the metrics, so the request itself is only drained
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request);
+                let body = metrics.prometheus();
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            }
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,512 @@
+use crate::entropy::Entropy;
+use crate::handles::{Handles, Status};
+use crate::matchmaker::{ConnType, Endpoints, Matched, Request, MATCHMAKER_MAX_REQ};
+use crate::metrics::ModuleMetrics;
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::future::FutureExt;
+use futures::stream::{Peekable, StreamExt};
+use futures_timer::Delay;
+use log::debug;
+use loopback::{Connection, LoopbackConfig, Stats};
+use protocols::*;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+type PeekRecv<T> = Peekable<Receiver<T>>;
+
+pub struct SocketManager {
+    listeners: HashMap<Handle, PeekRecv<(Matched, Endpoints)>>,
+    connectors: HashMap<Handle, PeekRecv<(Matched, Endpoints)>>,
+    sockets: HashMap<Handle, Connection>,
+    /// Closed sockets still sending what was written before they were closed
+    closing: Vec<Connection>,
+    /// Sockets whose last write, flush or shutdown had to wait for room, so the module wants to
+    /// hear once it can carry on
+    write_blocked: HashSet<Handle>,
+    /// Who is at each end of each socket
+    endpoints: HashMap<Handle, Endpoints>,
+    /// Description of the most recent failure on each handle
+    errors: HashMap<Handle, String>,
+    timers: HashMap<Handle, Delay>,
+    matchmaker: Sender<Request>,
+    handles: Handles,
+    id: ModuleId,
+    metrics: Arc<ModuleMetrics>,
+    loopback_config: LoopbackConfig,
+    entropy: Entropy,
+}
+
+impl SocketManager {
+    pub fn new(id: ModuleId, matchmaker: Sender<Request>, metrics: Arc<ModuleMetrics>) -> Self {
+        Self {
+            id,
+            matchmaker,
+            metrics,
+            handles: Handles::default(),
+            sockets: HashMap::new(),
+            closing: Vec::new(),
+            write_blocked: HashSet::new(),
+            endpoints: HashMap::new(),
+            errors: HashMap::new(),
+            listeners: HashMap::new(),
+            connectors: HashMap::new(),
+            timers: HashMap::new(),
+            loopback_config: Default::default(),
+            entropy: Default::default(),
+        }
+    }
+
+    /// Use these settings for connections made to this module's listeners
+    pub fn with_loopback_config(mut self, config: LoopbackConfig) -> Self {
+        self.loopback_config = config;
+        self
+    }
+
+    /// Give this module reproducible randomness from `seed`, rather than the operating system's.
+    /// Modules with different ids get different bytes from the same seed.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.set_seed(seed);
+        self
+    }
+
+    /// Like `with_seed`, for sockets which are already in use. Open handles are left alone.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.entropy = Entropy::seeded(seed, &self.id);
+    }
+
+    /// Hand out handles which fit in 32 bits, for modules from before ABI version 2. Closed
+    /// handles are never reused, so they are still reported as stale.
+    pub fn with_32_bit_handles(mut self) -> Self {
+        self.handles = Handles::narrow();
+        self
+    }
+
+    /// Id of the module these sockets belong to
+    pub fn id(&self) -> &ModuleId {
+        &self.id
+    }
+
+    /// Counters for each of the module's open sockets
+    pub fn stats(&self) -> Vec<(Handle, Stats)> {
+        let mut stats: Vec<_> = self
+            .sockets
+            .iter()
+            .map(|(handle, socket)| (*handle, socket.stats()))
+            .collect();
+        stats.sort_by_key(|(handle, _)| *handle);
+        stats
+    }
+
+    /// Returns true if the module has no open handles, and everything written to closed sockets
+    /// has been sent
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+            && self.connectors.is_empty()
+            && self.sockets.is_empty()
+            && self.timers.is_empty()
+            && self.closing.is_empty()
+    }
+
+    /// Returns true if the module is waiting on any timers
+    pub fn has_timers(&self) -> bool {
+        !self.timers.is_empty()
+    }
+
+    /// Update the open handle gauge after handles are created or closed
+    fn count_handles(&self) {
+        let open =
+            self.listeners.len() + self.connectors.len() + self.sockets.len() + self.timers.len();
+        self.metrics
+            .open_handles
+            .store(open as u64, Ordering::Relaxed);
+    }
+
+    /// Pass a request to the match maker, which may have stopped if the kernel is shutting down
+    fn request(&mut self, request: Request) -> io::Result<()> {
+        self.matchmaker.try_send(request).map_err(|e| {
+            if e.is_disconnected() {
+                io::Error::new(io::ErrorKind::NotConnected, "The match maker has stopped")
+            } else {
+                io::Error::other("Too many requests to the match maker")
+            }
+        })
+    }
+
+    fn create_handle(&mut self) -> Handle {
+        self.handles.create()
+    }
+
+    /// The error for a handle which doesn't name anything of the kind wanted. Closed handles get
+    /// their own error, so modules can tell use after close from other mistakes.
+    fn bad_handle(&self, handle: Handle) -> io::Error {
+        match self.handles.status(handle) {
+            Status::Stale => io::Error::new(
+                io::ErrorKind::StaleNetworkFileHandle,
+                format!("Handle {} has been closed", handle),
+            ),
+            _ => io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No such handle: {}", handle),
+            ),
+        }
+    }
+}
+
+impl SocketManager {
+    /// Initiate a new connection to a peer. Returns a handle that may be passed to listen().
+    pub fn connect(&mut self, addr: &str, port: Port) -> Poll<io::Result<Handle>> {
+        let (tx, rx) = channel(MATCHMAKER_MAX_REQ);
+        let sent = self.request(Request {
+            id: addr.to_string(),
+            from: self.id.clone(),
+            port,
+            conn_type: ConnType::Connector,
+            dest_socket: tx,
+            config: Default::default(),
+            typed: None,
+        });
+        if let Err(e) = sent {
+            return self.record_error(NO_HANDLE, Poll::Ready(Err(e)));
+        }
+        let new_handle = self.create_handle();
+        self.connectors.insert(new_handle, rx.peekable());
+        self.count_handles();
+        Poll::Ready(Ok(new_handle))
+    }
+
+    /// Like `connect`, for a peer name straight from a module, which may not be UTF-8
+    pub fn connect_raw(&mut self, addr: &[u8], port: Port) -> Poll<io::Result<Handle>> {
+        match std::str::from_utf8(addr) {
+            Ok(addr) => self.connect(addr, port),
+            Err(_) => {
+                let e = io::Error::new(io::ErrorKind::InvalidData, "Peer name is not valid UTF-8");
+                self.record_error(NO_HANDLE, Poll::Ready(Err(e)))
+            }
+        }
+    }
+
+    /// Create a new listener for a port. Calling this will create a listener that may be passed to
+    /// listen()
+    pub fn listener_create(&mut self, port: Port) -> Poll<io::Result<Handle>> {
+        let (tx, rx) = channel(MATCHMAKER_MAX_REQ);
+        let sent = self.request(Request {
+            id: self.id.clone(),
+            from: self.id.clone(),
+            port,
+            conn_type: ConnType::Listener,
+            dest_socket: tx,
+            config: self.loopback_config,
+            typed: None,
+        });
+        if let Err(e) = sent {
+            return self.record_error(NO_HANDLE, Poll::Ready(Err(e)));
+        }
+        let new_handle = self.create_handle();
+        self.listeners.insert(new_handle, rx.peekable());
+        self.count_handles();
+        Poll::Ready(Ok(new_handle))
+    }
+
+    /// Listen for a new connection on this handle.
+    pub fn listen(&mut self, handle: Handle, cx: &mut Context) -> Poll<io::Result<Handle>> {
+        let mut is_connector = false;
+        let listeners = &mut self.listeners;
+
+        let listener = self.connectors.get_mut(&handle).or_else(|| {
+            is_connector = true;
+            listeners.get_mut(&handle)
+        });
+
+        let poll = if let Some(listener) = listener {
+            match listener.poll_next_unpin(cx) {
+                Poll::Ready(Some((matched, endpoints))) => {
+                    if is_connector {
+                        listener.get_mut().close();
+                    }
+                    // Modules never ask for typed channels, so they are only given byte streams
+                    let conn = matched.bytes().expect("Typed channel sent to a module");
+                    let new_handle = self.create_handle();
+                    debug!(
+                        "{}: socket {} connected {:?}",
+                        self.id, new_handle, endpoints
+                    );
+                    self.sockets.insert(new_handle, conn);
+                    self.endpoints.insert(new_handle, endpoints);
+                    self.count_handles();
+                    Poll::Ready(Ok(new_handle))
+                }
+                Poll::Ready(None) => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "The match maker has stopped",
+                ))),
+                Poll::Pending => Poll::Pending,
+            }
+        } else {
+            Poll::Ready(Err(self.bad_handle(handle)))
+        };
+        self.record_error(handle, poll)
+    }
+
+    /// Close this handle. Sockets are shut down first, so the peer reads end of file rather than
+    /// a reset connection. Sockets with writes that don't fit yet are kept until they have been
+    /// sent, by `wakes`.
+    pub fn close(&mut self, handle: Handle) {
+        // Stale handles can't name anything, and their slot may belong to something else now
+        if !self.handles.release(handle) {
+            return;
+        }
+        self.listeners.remove(&handle);
+        self.connectors.remove(&handle);
+        self.timers.remove(&handle);
+        self.endpoints.remove(&handle);
+        self.errors.remove(&handle);
+        self.write_blocked.remove(&handle);
+        if let Some(mut socket) = self.sockets.remove(&handle) {
+            socket.shutdown();
+            debug!(
+                "{}: closed socket {}, {:?}",
+                self.id,
+                handle,
+                socket.stats()
+            );
+            self.closing.push(socket);
+        }
+        self.count_handles();
+    }
+
+    /// Remember that a write to `handle` has to wait for room, so `wakes` reports it writable
+    fn note_write_blocked<T>(&mut self, handle: Handle, poll: &Poll<T>) {
+        if poll.is_pending() {
+            self.write_blocked.insert(handle);
+        }
+    }
+
+    /// Remember the description of a failure on `handle`, for `last_error_message`. Only live
+    /// handles and `NO_HANDLE` keep one; anything else would never be closed, so never removed.
+    pub fn record_error<T>(
+        &mut self,
+        handle: Handle,
+        poll: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        if let Poll::Ready(Err(e)) = &poll {
+            debug!("{}: handle {} failed: {}", self.id, handle, e);
+            if handle == NO_HANDLE || self.handles.status(handle) == Status::Live {
+                self.errors.insert(handle, e.to_string());
+            }
+        }
+        poll
+    }
+
+    /// Copy the description of the most recent failure on `handle` into `buffer`, which may be
+    /// too short. Returns the full length of the description.
+    pub fn last_error_message(&self, handle: Handle, buffer: &[Cell<u8>]) -> Poll<io::Result<u32>> {
+        let message = self.errors.get(&handle).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "Nothing has failed on this handle")
+        })?;
+        for (cell, byte) in buffer.iter().zip(message.bytes()) {
+            cell.set(byte);
+        }
+        Poll::Ready(Ok(message.len() as u32))
+    }
+
+    /// Who is at each end of this socket, as assigned by the match maker
+    pub fn endpoints(&self, handle: Handle) -> io::Result<&Endpoints> {
+        self.endpoints
+            .get(&handle)
+            .ok_or_else(|| self.bad_handle(handle))
+    }
+
+    /// Copy the id of the module at one end of this socket into `buffer`, which may be too short.
+    /// Returns the full length of the id.
+    pub fn socket_module(
+        &self,
+        handle: Handle,
+        peer: bool,
+        buffer: &[Cell<u8>],
+    ) -> Poll<io::Result<u32>> {
+        let endpoints = self.endpoints(handle)?;
+        let addr = if peer {
+            &endpoints.peer
+        } else {
+            &endpoints.local
+        };
+        for (cell, byte) in buffer.iter().zip(addr.module.bytes()) {
+            cell.set(byte);
+        }
+        Poll::Ready(Ok(addr.module.len() as u32))
+    }
+
+    /// The port at one end of this socket
+    pub fn socket_port(&self, handle: Handle, peer: bool) -> Poll<io::Result<u32>> {
+        let endpoints = self.endpoints(handle)?;
+        let addr = if peer {
+            &endpoints.peer
+        } else {
+            &endpoints.local
+        };
+        Poll::Ready(Ok(addr.port as u32))
+    }
+
+    /// Read from this handle
+    pub fn read(
+        &mut self,
+        handle: Handle,
+        buffer: &[Cell<u8>],
+        cx: &mut Context,
+    ) -> Poll<io::Result<u32>> {
+        let poll = if let Some(socket) = self.sockets.get_mut(&handle) {
+            let mut tmp = vec![0u8; buffer.len()];
+            use futures::io::AsyncRead;
+            let res = Pin::new(socket).poll_read(cx, &mut tmp);
+            if let Poll::Ready(Ok(n)) = res {
+                for i in 0..n {
+                    buffer[i].set(tmp[i]);
+                }
+                // End of file isn't a message, as in the loopback's own stats
+                if n > 0 {
+                    self.metrics
+                        .bytes_read
+                        .fetch_add(n as u64, Ordering::Relaxed);
+                    self.metrics.messages_read.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            res.map(|n| n.map(|n| n as u32))
+        } else {
+            Poll::Ready(Err(self.bad_handle(handle)))
+        };
+        self.record_error(handle, poll)
+    }
+
+    /// Write to this handle
+    pub fn write(
+        &mut self,
+        handle: Handle,
+        buffer: &[u8],
+        cx: &mut Context,
+    ) -> Poll<io::Result<u32>> {
+        let poll = if let Some(socket) = self.sockets.get_mut(&handle) {
+            use futures::io::AsyncWrite;
+            let res = Pin::new(socket).poll_write(cx, buffer);
+            if let Poll::Ready(Ok(n)) = res {
+                self.metrics
+                    .bytes_written
+                    .fetch_add(n as u64, Ordering::Relaxed);
+                self.metrics
+                    .messages_written
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            res.map(|n| n.map(|n| n as u32))
+        } else {
+            Poll::Ready(Err(self.bad_handle(handle)))
+        };
+        self.note_write_blocked(handle, &poll);
+        self.record_error(handle, poll)
+    }
+
+    pub fn flush(&mut self, handle: Handle, cx: &mut Context) -> Poll<io::Result<()>> {
+        let poll = if let Some(socket) = self.sockets.get_mut(&handle) {
+            use futures::io::AsyncWrite;
+            Pin::new(socket).poll_flush(cx)
+        } else {
+            Poll::Ready(Err(self.bad_handle(handle)))
+        };
+        self.note_write_blocked(handle, &poll);
+        self.record_error(handle, poll)
+    }
+
+    /// Shut down the write side of this handle. It may still be read from.
+    pub fn shutdown(&mut self, handle: Handle, cx: &mut Context) -> Poll<io::Result<()>> {
+        let poll = if let Some(socket) = self.sockets.get_mut(&handle) {
+            use futures::io::AsyncWrite;
+            Pin::new(socket).poll_close(cx)
+        } else {
+            Poll::Ready(Err(self.bad_handle(handle)))
+        };
+        self.note_write_blocked(handle, &poll);
+        self.record_error(handle, poll)
+    }
+
+    /// Create a timer which becomes ready after `duration`. Calling this will create a handle that
+    /// may be passed to timer_poll()
+    pub fn timer_create(&mut self, duration: Duration) -> Poll<io::Result<Handle>> {
+        let new_handle = self.create_handle();
+        self.timers.insert(new_handle, Delay::new(duration));
+        self.count_handles();
+        Poll::Ready(Ok(new_handle))
+    }
+
+    /// Check whether this timer has fired
+    pub fn timer_poll(&mut self, handle: Handle, cx: &mut Context) -> Poll<io::Result<u32>> {
+        let poll = if let Some(timer) = self.timers.get_mut(&handle) {
+            timer.poll_unpin(cx).map(|()| Ok(0))
+        } else {
+            Poll::Ready(Err(self.bad_handle(handle)))
+        };
+        self.record_error(handle, poll)
+    }
+
+    /// Fill `buffer` with random bytes. Returns the number of bytes written.
+    pub fn random(&mut self, buffer: &[Cell<u8>]) -> Poll<io::Result<u32>> {
+        let mut tmp = vec![0u8; buffer.len()];
+        if let Err(e) = self.entropy.fill(&mut tmp) {
+            return self.record_error(NO_HANDLE, Poll::Ready(Err(e)));
+        }
+        for (cell, byte) in buffer.iter().zip(tmp) {
+            cell.set(byte);
+        }
+        Poll::Ready(Ok(buffer.len() as u32))
+    }
+
+    /// Return the handles that are supposed to be awake, and what each is ready for
+    pub fn wakes(&mut self, cx: &mut Context) -> Vec<(Handle, Interest)> {
+        // Finish shutting down closed sockets. Failures mean the peer has gone, so nobody is left
+        // to send to.
+        self.closing.retain_mut(|socket| {
+            use futures::io::AsyncWrite;
+            Pin::new(socket).poll_close(cx).is_pending()
+        });
+
+        // Abuse poll_peek() to determine whether there is data behind a socket/listener and wake
+        // the appropriate task(s)
+        let mut wakes: Vec<(Handle, Interest)> = self
+            .listeners
+            .iter_mut()
+            .chain(self.connectors.iter_mut())
+            .filter_map(|(handle, listener)| {
+                if Pin::new(&mut *listener).poll_peek(cx).is_ready() {
+                    Some((*handle, Interest::Acceptable))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        for (handle, socket) in self.sockets.iter_mut() {
+            if socket.is_readable(cx) {
+                wakes.push((*handle, Interest::Readable));
+            }
+            // Only wake writers which are waiting, or every idle socket would wake its module on
+            // every run
+            if self.write_blocked.contains(handle) && socket.is_writable(cx) {
+                wakes.push((*handle, Interest::Writable));
+            }
+        }
+        for (handle, interest) in &wakes {
+            if *interest == Interest::Writable {
+                self.write_blocked.remove(handle);
+            }
+        }
+        for (handle, timer) in self.timers.iter_mut() {
+            if timer.poll_unpin(cx).is_ready() {
+                wakes.push((*handle, Interest::Readable));
+            }
+        }
+        wakes
+    }
+}
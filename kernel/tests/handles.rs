@@ -0,0 +1,111 @@
+use futures::channel::mpsc::channel;
+use futures::task::noop_waker_ref;
+use kernel::handles::{Handles, Status};
+use kernel::metrics::ModuleMetrics;
+use kernel::socket::SocketManager;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+#[test]
+fn slots_are_reused_under_a_new_generation() {
+    let mut handles = Handles::default();
+    let first = handles.create();
+    let second = handles.create();
+    assert_ne!(first, second);
+
+    assert!(handles.release(first));
+    assert!(!handles.release(first));
+    assert_eq!(handles.status(first), Status::Stale);
+
+    let reused = handles.create();
+    assert_ne!(reused, first);
+    assert_eq!(reused as u32, first as u32);
+    assert_eq!(handles.status(first), Status::Stale);
+    assert_eq!(handles.status(reused), Status::Live);
+    assert_eq!(handles.status(second), Status::Live);
+    assert_eq!(handles.status(reused + (1 << 32)), Status::Unknown);
+    assert_eq!(handles.status(1000), Status::Unknown);
+}
+
+#[test]
+fn narrow_handles_count_up_without_reuse() {
+    let mut handles = Handles::narrow();
+    let first = handles.create();
+    assert_eq!(first, 0);
+    assert!(handles.release(first));
+    assert_eq!(handles.status(first), Status::Stale);
+
+    let second = handles.create();
+    assert_eq!(second, 1);
+    assert_eq!(handles.status(first), Status::Stale);
+    assert_eq!(handles.status(second), Status::Live);
+    assert_eq!(handles.status(2), Status::Unknown);
+    assert_eq!(handles.status(second + (1 << 32)), Status::Unknown);
+}
+
+fn sockets() -> SocketManager {
+    let (matchmaker, _) = channel(1);
+    SocketManager::new(
+        "module".into(),
+        matchmaker,
+        Arc::new(ModuleMetrics::default()),
+    )
+}
+
+fn error_kind<T>(poll: Poll<std::io::Result<T>>) -> Option<ErrorKind> {
+    match poll {
+        Poll::Ready(Err(e)) => Some(e.kind()),
+        _ => None,
+    }
+}
+
+#[test]
+fn stale_handles_are_turned_away() {
+    let mut sockets = sockets();
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let hour = Duration::from_secs(3600);
+
+    let old = match sockets.timer_create(hour) {
+        Poll::Ready(Ok(handle)) => handle,
+        _ => panic!("No timer"),
+    };
+    sockets.close(old);
+    let new = match sockets.timer_create(hour) {
+        Poll::Ready(Ok(handle)) => handle,
+        _ => panic!("No timer"),
+    };
+    assert_ne!(old, new);
+
+    assert_eq!(
+        error_kind(sockets.timer_poll(old, &mut cx)),
+        Some(ErrorKind::StaleNetworkFileHandle)
+    );
+    assert!(sockets.timer_poll(new, &mut cx).is_pending());
+
+    // Closing the stale handle leaves the new timer alone
+    sockets.close(old);
+    assert!(sockets.has_timers());
+    assert!(sockets.timer_poll(new, &mut cx).is_pending());
+
+    assert_eq!(
+        error_kind(sockets.timer_poll(new + 1, &mut cx)),
+        Some(ErrorKind::NotFound)
+    );
+}
+
+#[test]
+fn requests_fail_once_the_matchmaker_has_stopped() {
+    // The match maker's end of the channel is already gone
+    let mut sockets = sockets();
+    assert_eq!(
+        error_kind(sockets.connect("peer", 5)),
+        Some(ErrorKind::NotConnected)
+    );
+    assert_eq!(
+        error_kind(sockets.listener_create(5)),
+        Some(ErrorKind::NotConnected)
+    );
+    assert!(sockets.is_empty());
+}
@@ -0,0 +1,191 @@
+use futures::executor::LocalPool;
+use futures::future::poll_fn;
+use futures::task::{noop_waker_ref, SpawnExt};
+use kernel::matchmaker::MatchMaker;
+use kernel::metrics::ModuleMetrics;
+use kernel::socket::SocketManager;
+use loopback::LoopbackConfig;
+use protocols::{Handle, Interest};
+use std::cell::Cell;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Two modules with a socket between them
+struct Connected {
+    pool: LocalPool,
+    server: SocketManager,
+    server_socket: Handle,
+    client: SocketManager,
+    client_socket: Handle,
+    client_metrics: Arc<ModuleMetrics>,
+}
+
+fn connected() -> Connected {
+    let mut pool = LocalPool::new();
+    let (matchmaker, conn) = MatchMaker::new();
+    pool.spawner().spawn(matchmaker.task()).unwrap();
+
+    // Buffered, so writes can be left waiting to be sent
+    let mut server = SocketManager::new(
+        "server".into(),
+        conn.clone(),
+        Arc::new(ModuleMetrics::default()),
+    )
+    .with_loopback_config(LoopbackConfig {
+        flush_threshold: 4096,
+        ..Default::default()
+    });
+    let client_metrics = Arc::new(ModuleMetrics::default());
+    let mut client = SocketManager::new("client".into(), conn, client_metrics.clone());
+
+    let listener = ready(server.listener_create(5));
+    let connector = ready(client.connect("server", 5));
+    let server_socket = pool
+        .run_until(poll_fn(|cx| server.listen(listener, cx)))
+        .unwrap();
+    let client_socket = pool
+        .run_until(poll_fn(|cx| client.listen(connector, cx)))
+        .unwrap();
+    // Leave only the sockets open
+    server.close(listener);
+    client.close(connector);
+    Connected {
+        pool,
+        server,
+        server_socket,
+        client,
+        client_socket,
+        client_metrics,
+    }
+}
+
+fn ready<T>(poll: Poll<std::io::Result<T>>) -> T {
+    match poll {
+        Poll::Ready(Ok(value)) => value,
+        _ => panic!("Not ready"),
+    }
+}
+
+#[test]
+fn end_of_file_is_not_counted_as_a_message() {
+    let Connected {
+        mut pool,
+        mut server,
+        server_socket,
+        mut client,
+        client_socket,
+        client_metrics,
+    } = connected();
+    let mut cx = Context::from_waker(noop_waker_ref());
+
+    ready(server.write(server_socket, b"hi", &mut cx));
+    ready(server.shutdown(server_socket, &mut cx));
+
+    let buffer = vec![Cell::new(0u8); 16];
+    let read = pool.run_until(poll_fn(|cx| client.read(client_socket, &buffer, cx)));
+    assert_eq!(read.unwrap(), 2);
+    let read = pool.run_until(poll_fn(|cx| client.read(client_socket, &buffer, cx)));
+    assert_eq!(read.unwrap(), 0);
+
+    assert_eq!(client_metrics.messages_read.load(Ordering::Relaxed), 1);
+    assert_eq!(client_metrics.bytes_read.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn closing_sends_everything_written() {
+    let Connected {
+        mut pool,
+        mut server,
+        server_socket,
+        mut client,
+        client_socket,
+        ..
+    } = connected();
+    let mut cx = Context::from_waker(noop_waker_ref());
+
+    // More than the ring holds, so some of it is still waiting when the socket is closed
+    let sent: Vec<u8> = (0..40_000u32).map(|i| i as u8).collect();
+    let mut written = 0;
+    while let Poll::Ready(Ok(n)) = server.write(server_socket, &sent[written..], &mut cx) {
+        written += n as usize;
+    }
+    server.close(server_socket);
+    assert!(!server.is_empty());
+
+    let buffer = vec![Cell::new(0u8); 4096];
+    let mut received = Vec::new();
+    loop {
+        server.wakes(&mut cx);
+        let read = pool.run_until(poll_fn(|cx| client.read(client_socket, &buffer, cx)));
+        match read.unwrap() {
+            0 => break,
+            n => received.extend(buffer[..n as usize].iter().map(Cell::get)),
+        }
+    }
+    assert_eq!(received, &sent[..written]);
+    assert!(server.is_empty());
+}
+
+#[test]
+fn only_blocked_writers_are_woken() {
+    let Connected {
+        mut pool,
+        mut server,
+        server_socket,
+        mut client,
+        client_socket,
+        ..
+    } = connected();
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let writable = |server: &mut SocketManager, cx: &mut Context| {
+        server
+            .wakes(cx)
+            .contains(&(server_socket, Interest::Writable))
+    };
+
+    // Room to write isn't news to a module which hasn't been turned away
+    assert!(!writable(&mut server, &mut cx));
+
+    let data = [7u8; 4096];
+    while server.write(server_socket, &data, &mut cx).is_ready() {}
+    assert!(!writable(&mut server, &mut cx));
+
+    let buffer = vec![Cell::new(0u8); 16384];
+    pool.run_until(poll_fn(|cx| client.read(client_socket, &buffer, cx)))
+        .unwrap();
+    assert!(writable(&mut server, &mut cx));
+    assert!(!writable(&mut server, &mut cx));
+}
+
+#[test]
+fn errors_are_kept_only_for_live_handles() {
+    let Connected {
+        mut server,
+        server_socket,
+        ..
+    } = connected();
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let buffer = vec![Cell::new(0u8); 64];
+
+    // The failure is still reported, but nothing is left behind for a handle nobody can close
+    let unknown = 12345;
+    assert!(matches!(
+        server.write(unknown, b"hi", &mut cx),
+        Poll::Ready(Err(_))
+    ));
+    assert!(matches!(
+        server.last_error_message(unknown, &buffer),
+        Poll::Ready(Err(_))
+    ));
+
+    server.close(server_socket);
+    assert!(matches!(
+        server.write(server_socket, b"hi", &mut cx),
+        Poll::Ready(Err(_))
+    ));
+    assert!(matches!(
+        server.last_error_message(server_socket, &buffer),
+        Poll::Ready(Err(_))
+    ));
+}
@@ -0,0 +1,26 @@
+use protocols::abi;
+
+/// Version of the interface to the host this module was built against
+#[no_mangle]
+pub extern "C" fn abi_version() -> u32 {
+    abi::VERSION
+}
+
+/// Optional parts of the interface this module may use, as chosen by libplugin's features
+#[no_mangle]
+pub extern "C" fn abi_features() -> u64 {
+    let mut features = 0;
+    if cfg!(feature = "timers") {
+        features |= abi::TIMERS;
+    }
+    if cfg!(feature = "random") {
+        features |= abi::RANDOM;
+    }
+    if cfg!(feature = "peer-addr") {
+        features |= abi::PEER_ADDR;
+    }
+    if cfg!(feature = "error-messages") {
+        features |= abi::ERROR_MESSAGES;
+    }
+    features
+}
@@ -0,0 +1,55 @@
+//! Errors from the host's imports, described with the host's own message where it has one
+
+use protocols::Handle;
+#[cfg(any(feature = "error-messages", feature = "peer-addr"))]
+use protocols::Maybe;
+use std::io;
+use std::task::Poll;
+
+#[cfg(feature = "error-messages")]
+use crate::imports::last_error_message;
+
+/// The result of an import on `handle`, decoded with `Maybe::errorkind` or `Maybe::handle`
+pub(crate) fn result<T>(retval: Result<T, io::ErrorKind>, handle: Handle) -> io::Result<T> {
+    match retval {
+        Ok(n) => Ok(n),
+        Err(io::ErrorKind::WouldBlock) => Err(io::ErrorKind::WouldBlock.into()),
+        Err(kind) => Err(match message(handle) {
+            Some(message) => io::Error::new(kind, message),
+            None => kind.into(),
+        }),
+    }
+}
+
+/// The result of an import on `handle` which may not be ready yet
+pub(crate) fn poll<T>(retval: Result<T, io::ErrorKind>, handle: Handle) -> Poll<io::Result<T>> {
+    match result(retval, handle) {
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+        other => Poll::Ready(other),
+    }
+}
+
+/// The host's description of the most recent failure on `handle`
+#[cfg(feature = "error-messages")]
+fn message(handle: Handle) -> Option<String> {
+    string(|buf| unsafe { last_error_message(handle, buf.as_mut_ptr(), buf.len()) }).ok()
+}
+
+#[cfg(not(feature = "error-messages"))]
+fn message(_handle: Handle) -> Option<String> {
+    None
+}
+
+/// Fetch a string from an import which fills a buffer and returns the string's full length
+#[cfg(any(feature = "error-messages", feature = "peer-addr"))]
+pub(crate) fn string(mut import: impl FnMut(&mut [u8]) -> Maybe) -> io::Result<String> {
+    let mut buf = vec![0u8; 64];
+    loop {
+        let len = import(&mut buf).errorkind()? as usize;
+        if len <= buf.len() {
+            buf.truncate(len);
+            return String::from_utf8(buf).map_err(|_| io::ErrorKind::InvalidData.into());
+        }
+        buf.resize(len, 0);
+    }
+}
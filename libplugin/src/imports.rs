@@ -0,0 +1,6 @@
+//! The host's imports, generated from the declarations in `protocols::host_imports!`. Natively
+//! they are served by the mock kernel.
+
+use protocols::guest_imports;
+
+protocols::host_imports!(guest_imports, crate::mock::Host);
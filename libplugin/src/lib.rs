@@ -1,10 +1,32 @@
+mod abi;
 mod debug;
+mod error;
+mod imports;
+mod lifecycle;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod mock;
+mod nursery;
+#[cfg(feature = "random")]
+pub mod random;
 mod reactor;
+mod runtime;
 mod socket;
 mod task_pool;
+#[cfg(feature = "timers")]
+mod time;
+#[doc(hidden)]
+pub use abi::{abi_features, abi_version};
 pub use debug::debug;
-pub use socket::{Socket, SocketListener};
-pub use task_pool::{spawn, yield_now};
+pub use libplugin_macros::main;
+pub use lifecycle::host_string;
+pub use nursery::Nursery;
+pub use protocols::{Addr, ModuleId, Port};
+pub use socket::{ReadHalf, Socket, SocketListener, WriteHalf};
+#[doc(hidden)]
+pub use task_pool::start;
+pub use task_pool::{spawn, yield_now, JoinError, JoinHandle};
+#[cfg(feature = "timers")]
+pub use time::{sleep, timeout, Sleep, TimedOut, Timeout};
 
 pub use futures::io::{AsyncReadExt, AsyncWriteExt};
 pub use futures::stream::StreamExt;
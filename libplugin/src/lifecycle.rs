@@ -0,0 +1,25 @@
+//! Support for the optional lifecycle exports a module may define:
+//!
+//! * `init(config: *mut u8, len: u32)`: called before `main` with the module's config as TOML
+//!   text. Dependencies may not be listening yet.
+//! * `on_shutdown(grace_ms: u32)`: the kernel is shutting down, and will stop the module after
+//!   the grace period.
+//! * `on_peer_down(peer: *mut u8, len: u32)`: the module with the given id has stopped.
+//!
+//! String arguments are allocated through `alloc()` and should be reclaimed with `host_string()`.
+
+/// Allocate a buffer for the host to pass a string into the module
+#[no_mangle]
+pub extern "C" fn alloc(len: u32) -> *mut u8 {
+    Box::into_raw(vec![0u8; len as usize].into_boxed_slice()) as *mut u8
+}
+
+/// Take ownership of a string passed to a lifecycle export by the host.
+///
+/// # Safety
+/// `ptr` and `len` must be exactly as passed to the export by the host, and only used once.
+pub unsafe fn host_string(ptr: *mut u8, len: u32) -> String {
+    let buf = Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len as usize));
+    String::from_utf8(buf.into_vec())
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
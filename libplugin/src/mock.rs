@@ -0,0 +1,259 @@
+//! An in-process stand-in for the kernel, so modules can be tested natively with `cargo test`.
+//!
+//! Each thread gets its own match maker, with the module under test registered as `MODULE`.
+//! Tests play the part of other modules with `connect` and `listen`, and `block_on` runs the
+//! module's tasks alongside the test until the test's future completes:
+//!
+//! ```ignore
+//! spawn(server());
+//! let reply = mock::block_on(async {
+//!     let mut conn = mock::connect(5062).await.unwrap();
+//!     conn.write_all(b"hello").await.unwrap();
+//!     // ...
+//! });
+//! ```
+
+use crate::runtime::Runtime;
+use futures::executor::LocalPool;
+use futures::stream::Stream;
+use futures::task::{waker, ArcWake, LocalSpawnExt};
+use kernel::matchmaker::{self, MatchMaker, MatchMakerConnection};
+use kernel::metrics::{MetricsSnapshot, ModuleMetrics};
+use kernel::socket::SocketManager;
+use loopback::Connection;
+use protocols::idl::Imports;
+use protocols::{Handle, Port};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// Id of the module under test
+pub const MODULE: &str = "module";
+
+/// Rounds in a row without the module doing any I/O or the test being woken before `block_on`
+/// gives up. Ready sockets are woken every round, so some rounds are spent on spurious wakes.
+const STALLED_ROUNDS: u32 = 100;
+
+struct Kernel {
+    /// Runs the match maker
+    pool: RefCell<LocalPool>,
+    sockets: RefCell<SocketManager>,
+    matchmaker: MatchMakerConnection,
+    metrics: Arc<ModuleMetrics>,
+}
+
+thread_local! {
+    static KERNEL: Kernel = Kernel::new();
+}
+
+impl Kernel {
+    fn new() -> Self {
+        let (matchmaker, connection) = MatchMaker::new();
+        let pool = LocalPool::new();
+        pool.spawner().spawn_local(matchmaker.task()).unwrap();
+        let metrics = Arc::new(ModuleMetrics::default());
+        let sockets = SocketManager::new(MODULE.into(), connection.clone(), metrics.clone());
+        Self {
+            pool: RefCell::new(pool),
+            sockets: RefCell::new(sockets),
+            matchmaker: connection,
+            metrics,
+        }
+    }
+}
+
+/// The module's sockets are polled by `run_until_stalled` rather than woken
+fn with_sockets<R>(f: impl FnOnce(&mut SocketManager, &mut Context) -> R) -> R {
+    let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+    KERNEL.with(|kernel| f(&mut kernel.sockets.borrow_mut(), &mut cx))
+}
+
+/// Connect to a port on the module under test
+pub fn connect(port: Port) -> impl Future<Output = io::Result<Connection>> {
+    connect_to(MODULE, port)
+}
+
+/// Connect to a port on the module under test as the module `from`
+pub fn connect_as(from: &str, port: Port) -> impl Future<Output = io::Result<Connection>> {
+    let from = from.to_string();
+    let mut matchmaker = KERNEL.with(|kernel| kernel.matchmaker.clone());
+    async move {
+        matchmaker::connect_from(from, MODULE, port, &mut matchmaker)
+            .await
+            .ok()
+            .flatten()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+}
+
+/// Connect to a port on any module, including listeners made with `listen`
+pub fn connect_to(id: &str, port: Port) -> impl Future<Output = io::Result<Connection>> {
+    let id = id.to_string();
+    let mut matchmaker = KERNEL.with(|kernel| kernel.matchmaker.clone());
+    async move {
+        matchmaker::connect(id, port, &mut matchmaker)
+            .await
+            .ok()
+            .flatten()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+}
+
+/// Listen on a port as the module `id`, so the module under test can connect to it
+pub fn listen(id: &str, port: Port) -> impl Future<Output = impl Stream<Item = Connection>> {
+    let id = id.to_string();
+    let mut matchmaker = KERNEL.with(|kernel| kernel.matchmaker.clone());
+    async move {
+        matchmaker::create_listener(id, port, &mut matchmaker)
+            .await
+            .expect("The match maker has stopped")
+    }
+}
+
+/// Run the module's tasks and the match maker until neither can make progress without the
+/// test, and wake the module's sockets which are ready. Returns true if any were woken.
+pub fn run_until_stalled() -> bool {
+    crate::task_pool::run_tasks();
+    KERNEL.with(|kernel| kernel.pool.borrow_mut().run_until_stalled());
+    let ready = with_sockets(|sockets, cx| sockets.wakes(cx));
+    let mut woken = false;
+    for (handle, interest) in ready {
+        let waker = Runtime::with(|rt| rt.reactor.borrow_mut().take(handle, interest));
+        if let Some(waker) = waker {
+            waker.wake();
+            woken = true;
+        }
+    }
+    woken
+}
+
+struct Flag(AtomicBool);
+
+impl ArcWake for Flag {
+    fn wake_by_ref(flag: &Arc<Self>) {
+        flag.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Give the module under test reproducible randomness from `seed`
+pub fn seed(seed: u64) {
+    with_sockets(|sockets, _| sockets.set_seed(seed));
+}
+
+/// Counters of the module under test
+pub fn metrics() -> MetricsSnapshot {
+    KERNEL.with(|kernel| kernel.metrics.snapshot())
+}
+
+/// Run `future` as a peer of the module under test, returning its output. Panics if the module
+/// and the future are both stuck waiting.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let flag = Arc::new(Flag(AtomicBool::new(true)));
+    let waker = waker(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut last = metrics();
+    let mut stalled = 0;
+    loop {
+        let woken = run_until_stalled();
+        let polled = flag.0.swap(false, Ordering::SeqCst);
+        if polled {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+
+        let now = metrics();
+        if polled || now != last {
+            stalled = 0;
+        } else {
+            stalled += 1;
+        }
+        last = now;
+        if !woken && !polled || stalled >= STALLED_ROUNDS {
+            if !with_sockets(|sockets, _| sockets.has_timers()) {
+                panic!("Deadlock: the module and the test are both waiting");
+            }
+            // Nothing to do until a timer fires
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+/// The kernel's side of libplugin's imports
+pub(crate) struct Host;
+
+impl Imports for Host {
+    fn connect(&mut self, peer: &[u8], port: u16) -> Poll<io::Result<Handle>> {
+        with_sockets(|sockets, _| sockets.connect_raw(peer, port))
+    }
+
+    fn listener_create(&mut self, port: u16) -> Poll<io::Result<Handle>> {
+        with_sockets(|sockets, _| sockets.listener_create(port))
+    }
+
+    fn listen(&mut self, handle: Handle) -> Poll<io::Result<Handle>> {
+        with_sockets(|sockets, cx| sockets.listen(handle, cx))
+    }
+
+    fn close(&mut self, handle: Handle) {
+        // Sockets dropped as the thread exits may outlive the kernel
+        let _ = KERNEL.try_with(|kernel| kernel.sockets.borrow_mut().close(handle));
+    }
+
+    fn read(&mut self, handle: Handle, buffer: &[Cell<u8>]) -> Poll<io::Result<u32>> {
+        with_sockets(|sockets, cx| sockets.read(handle, buffer, cx))
+    }
+
+    fn write(&mut self, handle: Handle, buffer: &[u8]) -> Poll<io::Result<u32>> {
+        with_sockets(|sockets, cx| sockets.write(handle, buffer, cx))
+    }
+
+    fn flush(&mut self, handle: Handle) -> Poll<io::Result<u32>> {
+        let poll = with_sockets(|sockets, cx| sockets.flush(handle, cx));
+        poll.map(|v| v.map(|_| 0))
+    }
+
+    fn shutdown(&mut self, handle: Handle) -> Poll<io::Result<u32>> {
+        let poll = with_sockets(|sockets, cx| sockets.shutdown(handle, cx));
+        poll.map(|v| v.map(|_| 0))
+    }
+
+    fn timer_create(&mut self, ms: u32) -> Poll<io::Result<Handle>> {
+        let duration = Duration::from_millis(ms as u64);
+        with_sockets(|sockets, _| sockets.timer_create(duration))
+    }
+
+    fn timer_poll(&mut self, handle: Handle) -> Poll<io::Result<u32>> {
+        with_sockets(|sockets, cx| sockets.timer_poll(handle, cx))
+    }
+
+    fn random(&mut self, buffer: &[Cell<u8>]) -> Poll<io::Result<u32>> {
+        with_sockets(|sockets, _| sockets.random(buffer))
+    }
+
+    fn socket_module(
+        &mut self,
+        handle: Handle,
+        peer: u32,
+        buffer: &[Cell<u8>],
+    ) -> Poll<io::Result<u32>> {
+        with_sockets(|sockets, _| sockets.socket_module(handle, peer != 0, buffer))
+    }
+
+    fn socket_port(&mut self, handle: Handle, peer: u32) -> Poll<io::Result<u32>> {
+        with_sockets(|sockets, _| sockets.socket_port(handle, peer != 0))
+    }
+
+    fn last_error_message(&mut self, handle: Handle, buffer: &[Cell<u8>]) -> Poll<io::Result<u32>> {
+        with_sockets(|sockets, _| sockets.last_error_message(handle, buffer))
+    }
+
+    fn debug(&mut self, text: &[u8]) {
+        println!("Module debug {}", String::from_utf8_lossy(text));
+    }
+}
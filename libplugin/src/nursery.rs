@@ -0,0 +1,89 @@
+use crate::task_pool::{spawn, JoinHandle};
+use futures::channel::oneshot;
+use futures::future::{self, AbortHandle, FutureExt};
+use std::future::Future;
+use std::task::Poll;
+
+struct Child {
+    abort: AbortHandle,
+    /// Resolves once the child has finished or been dropped
+    done: oneshot::Receiver<()>,
+}
+
+/// A group of tasks which are cancelled together. Dropping the nursery cancels any children
+/// which are still running.
+///
+/// ```ignore
+/// let mut nursery = Nursery::new();
+/// while let Some(Ok(socket)) = listener.next().await {
+///     nursery.spawn(handle_connection(socket));
+/// }
+/// ```
+#[derive(Default)]
+pub struct Nursery {
+    children: Vec<Child>,
+}
+
+impl Nursery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawn a child task
+    pub fn spawn<F: Future + 'static>(&mut self, f: F) -> JoinHandle<F::Output> {
+        // Forget children which have already finished
+        self.children = self
+            .children
+            .drain(..)
+            .filter_map(|mut child| match child.done.try_recv() {
+                Ok(None) => Some(child),
+                _ => None,
+            })
+            .collect();
+
+        let (tx, done) = oneshot::channel();
+        let handle = spawn(async move {
+            let _done = tx;
+            f.await
+        });
+        self.children.push(Child {
+            abort: handle.abort_handle(),
+            done,
+        });
+        handle
+    }
+
+    /// Cancel every child
+    pub fn cancel(&mut self) {
+        for child in self.children.drain(..) {
+            child.abort.abort();
+        }
+    }
+
+    /// Wait for every child to finish or be cancelled
+    pub async fn join(&mut self) {
+        future::poll_fn(|cx| {
+            // Children stay in the nursery until they finish, in case we stop waiting
+            self.children = self
+                .children
+                .drain(..)
+                .filter_map(|mut child| match child.done.poll_unpin(cx) {
+                    Poll::Pending => Some(child),
+                    Poll::Ready(_) => None,
+                })
+                .collect();
+            if self.children.is_empty() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl Drop for Nursery {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
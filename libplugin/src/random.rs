@@ -0,0 +1,39 @@
+//! Randomness from the host. When the host is seeded, every run of a module sees the same bytes.
+//!
+//! This is also registered as `getrandom`'s backend, so `rand::thread_rng()` and other users of
+//! getrandom 0.2 work inside modules. Other targets have their own source, and getrandom only
+//! calls the backend on wasm.
+
+use crate::error;
+use crate::imports::random;
+use protocols::{Maybe, NO_HANDLE};
+use std::io;
+
+/// Fill `buf` with random bytes
+pub fn fill(buf: &mut [u8]) -> io::Result<()> {
+    let ret: Maybe = unsafe { random(buf.as_mut_ptr(), buf.len()) };
+    error::result(ret.errorkind(), NO_HANDLE).map(|_| ())
+}
+
+/// A random `u32`
+pub fn u32() -> u32 {
+    let mut buf = [0u8; 4];
+    fill(&mut buf).expect("The host has no randomness");
+    u32::from_le_bytes(buf)
+}
+
+/// A random `u64`, such as a seed for a faster generator
+pub fn u64() -> u64 {
+    let mut buf = [0u8; 8];
+    fill(&mut buf).expect("The host has no randomness");
+    u64::from_le_bytes(buf)
+}
+
+fn getrandom_host(buf: &mut [u8]) -> Result<(), getrandom::Error> {
+    fill(buf).map_err(|_| {
+        let code = std::num::NonZeroU32::new(getrandom::Error::CUSTOM_START).unwrap();
+        getrandom::Error::from(code)
+    })
+}
+
+getrandom::register_custom_getrandom!(getrandom_host);
@@ -1,17 +1,24 @@
-use protocols::Handle;
-use once_cell::unsync::Lazy;
+use crate::runtime::Runtime;
+use protocols::{Handle, Interest};
 use std::collections::HashMap;
 use std::task::Waker;
 
-pub static mut REACTOR: Lazy<Reactor> = Lazy::new(Reactor::new);
-
 #[no_mangle]
-pub unsafe extern "C" fn wake(handle: Handle) {
-    REACTOR.wake(handle);
+pub extern "C" fn wake(handle: Handle, interest: u32) {
+    let interest = match Interest::from_u32(interest) {
+        Some(interest) => interest,
+        None => return,
+    };
+    // Release the reactor before waking, in case the waker touches it
+    let waker = Runtime::with(|rt| rt.reactor.borrow_mut().take(handle, interest));
+    if let Some(waker) = waker {
+        waker.wake();
+    }
 }
 
+/// Wakers of the tasks waiting on each handle, one for each kind of readiness
 pub struct Reactor {
-    wakers: HashMap<Handle, Waker>,
+    wakers: HashMap<(Handle, Interest), Waker>,
 }
 
 impl Reactor {
@@ -21,20 +28,16 @@ impl Reactor {
         }
     }
 
-    pub fn register(&mut self, handle: Handle, waker: Waker) {
-        self.wakers.insert(handle, waker);
+    pub fn register(&mut self, handle: Handle, interest: Interest, waker: Waker) {
+        self.wakers.insert((handle, interest), waker);
     }
 
     // TODO: Use this from run_tasks and do it in one batch!
-    pub fn wake(&mut self, handle: Handle) {
-        if let Some(waker) = self.wakers.remove(&handle) {
-            waker.wake();
-        }
+    pub fn take(&mut self, handle: Handle, interest: Interest) -> Option<Waker> {
+        self.wakers.remove(&(handle, interest))
     }
 }
 
-pub fn register(handle: Handle, waker: Waker) {
-    unsafe {
-        REACTOR.register(handle, waker);
-    }
+pub fn register(handle: Handle, interest: Interest, waker: Waker) {
+    Runtime::with(|rt| rt.reactor.borrow_mut().register(handle, interest, waker));
 }
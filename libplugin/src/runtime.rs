@@ -0,0 +1,36 @@
+//! The module's single-threaded runtime. Wasm modules have one thread, so the runtime lives in a
+//! thread local and is only ever borrowed for the duration of a call.
+
+use crate::reactor::Reactor;
+use futures::executor::{LocalPool, LocalSpawner};
+use std::cell::{Cell, RefCell};
+
+pub struct Runtime {
+    pub pool: RefCell<LocalPool>,
+    pub spawner: LocalSpawner,
+    /// Number of spawned tasks which have not yet completed
+    pub tasks: Cell<u32>,
+    pub reactor: RefCell<Reactor>,
+}
+
+thread_local! {
+    static RUNTIME: Runtime = Runtime::new();
+}
+
+impl Runtime {
+    fn new() -> Self {
+        let pool = LocalPool::new();
+        let spawner = pool.spawner();
+        Self {
+            pool: RefCell::new(pool),
+            spawner,
+            tasks: Cell::new(0),
+            reactor: RefCell::new(Reactor::new()),
+        }
+    }
+
+    /// Run `f` with the runtime
+    pub fn with<R>(f: impl FnOnce(&Runtime) -> R) -> R {
+        RUNTIME.with(f)
+    }
+}
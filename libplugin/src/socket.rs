@@ -1,24 +1,19 @@
-use protocols::Maybe;
+use crate::error;
+use crate::imports::{close, connect, flush, listen, listener_create, read, shutdown, write};
+#[cfg(feature = "peer-addr")]
+use crate::imports::{socket_module, socket_port};
 use crate::reactor;
-use protocols::Handle;
 use futures::future::{self, Future};
 use futures::io::{AsyncRead, AsyncWrite};
 use futures::Stream;
+#[cfg(feature = "peer-addr")]
+use protocols::{Addr, ModuleId};
+use protocols::{Handle, Interest, NO_HANDLE};
 use std::io;
 use std::pin::Pin;
+use std::rc::Rc;
 use std::task::{Context, Poll};
 
-extern "C" {
-    fn connect(peer: *const u8, len: usize, port: u16) -> Maybe;
-    fn listener_create(port: u16) -> Maybe;
-    fn listen(handle: Handle) -> Maybe;
-    fn close(handle: Handle);
-
-    fn read(handle: Handle, buffer: *mut u8, len: usize) -> Maybe;
-    fn write(handle: Handle, buffer: *const u8, len: usize) -> Maybe;
-    fn flush(handle: Handle) -> Maybe;
-}
-
 pub struct Socket {
     handle: Handle,
 }
@@ -27,10 +22,15 @@ pub struct SocketListener {
     handle: Handle,
 }
 
-fn poll_ffi(retval: Maybe, handle: Handle, cx: &Context) -> Poll<io::Result<u32>> {
-    let poll = retval.into_poll();
+fn poll_ffi<T>(
+    retval: Result<T, io::ErrorKind>,
+    handle: Handle,
+    interest: Interest,
+    cx: &Context,
+) -> Poll<io::Result<T>> {
+    let poll = error::poll(retval, handle);
     if poll.is_pending() {
-        reactor::register(handle, cx.waker().clone());
+        reactor::register(handle, interest, cx.waker().clone());
     }
     poll
 }
@@ -40,24 +40,90 @@ impl Socket {
         peer: &'a str,
         port: u16,
     ) -> io::Result<impl Future<Output = io::Result<Self>> + 'a> {
-        let handle = unsafe { connect(peer.as_ptr(), peer.len(), port) }
-            .errorkind()
-            .map_err(io::Error::from)?;
+        let handle = error::result(
+            unsafe { connect(peer.as_ptr(), peer.len(), port) }.handle(),
+            NO_HANDLE,
+        )?;
 
         Ok(future::poll_fn(move |cx| {
-            let poll = poll_ffi(unsafe { listen(handle) }, handle, cx);
+            let ret = unsafe { listen(handle) }.handle();
+            let poll = poll_ffi(ret, handle, Interest::Acceptable, cx);
             if poll.is_ready() {
                 unsafe {
                     close(handle);
                 }
             }
-            poll.map(|result| {
-                result.map(|handle| Self {
-                    handle: handle as Handle,
-                })
-            })
+            poll.map(|result| result.map(|handle| Self { handle }))
         }))
     }
+
+    #[cfg(feature = "peer-addr")]
+    /// The module at the other end, as known to the kernel. Empty if it is the host itself.
+    pub fn peer_module(&self) -> io::Result<ModuleId> {
+        self.module(true)
+    }
+
+    #[cfg(feature = "peer-addr")]
+    /// This module's end of the connection. Connecting sockets are given a port by the kernel.
+    pub fn local_addr(&self) -> io::Result<Addr> {
+        self.addr(false)
+    }
+
+    #[cfg(feature = "peer-addr")]
+    /// The other end of the connection
+    pub fn peer_addr(&self) -> io::Result<Addr> {
+        self.addr(true)
+    }
+
+    #[cfg(feature = "peer-addr")]
+    fn addr(&self, peer: bool) -> io::Result<Addr> {
+        Ok(Addr {
+            module: self.module(peer)?,
+            port: error::result(
+                unsafe { socket_port(self.handle, peer as u32) }.errorkind(),
+                self.handle,
+            )? as u16,
+        })
+    }
+
+    #[cfg(feature = "peer-addr")]
+    fn module(&self, peer: bool) -> io::Result<ModuleId> {
+        error::string(|buf| unsafe {
+            socket_module(self.handle, peer as u32, buf.as_mut_ptr(), buf.len())
+        })
+    }
+
+    /// Split into halves which can be read and written from different tasks. The socket closes
+    /// once both are dropped.
+    pub fn split(self) -> (ReadHalf, WriteHalf) {
+        let socket = Rc::new(self);
+        (
+            ReadHalf {
+                socket: socket.clone(),
+            },
+            WriteHalf { socket },
+        )
+    }
+
+    fn raw_read(&self, cx: &Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let ret = unsafe { read(self.handle, buf.as_mut_ptr(), buf.len()) }.errorkind();
+        poll_ffi(ret, self.handle, Interest::Readable, cx).map(|v| v.map(|v| v as usize))
+    }
+
+    fn raw_write(&self, cx: &Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let ret = unsafe { write(self.handle, buf.as_ptr(), buf.len()) }.errorkind();
+        poll_ffi(ret, self.handle, Interest::Writable, cx).map(|v| v.map(|v| v as usize))
+    }
+
+    fn raw_flush(&self, cx: &Context) -> Poll<io::Result<()>> {
+        let ret = unsafe { flush(self.handle) }.errorkind();
+        poll_ffi(ret, self.handle, Interest::Writable, cx).map(|v| v.map(|_| ()))
+    }
+
+    fn raw_shutdown(&self, cx: &Context) -> Poll<io::Result<()>> {
+        let ret = unsafe { shutdown(self.handle) }.errorkind();
+        poll_ffi(ret, self.handle, Interest::Writable, cx).map(|v| v.map(|_| ()))
+    }
 }
 
 impl Drop for Socket {
@@ -68,18 +134,16 @@ impl Drop for Socket {
 
 impl AsyncWrite for Socket {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
-        let ret = unsafe { write(self.handle, buf.as_ptr(), buf.len()) };
-        poll_ffi(ret, self.handle, cx).map(|v| v.map(|v| v as usize))
+        self.raw_write(cx, buf)
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
-        let ret = unsafe { flush(self.handle) };
-        poll_ffi(ret, self.handle, cx).map(|v| v.map(|_| ()))
+        self.raw_flush(cx)
     }
 
-    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
-        unsafe { close(self.handle) };
-        Poll::Ready(Ok(()))
+    /// Shut down the write side. The peer reads end of file, and may still reply.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.raw_shutdown(cx)
     }
 }
 
@@ -89,8 +153,95 @@ impl AsyncRead for Socket {
         cx: &mut Context,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        let ret = unsafe { read(self.handle, buf.as_mut_ptr(), buf.len()) };
-        poll_ffi(ret, self.handle, cx).map(|v| v.map(|v| v as usize))
+        self.raw_read(cx, buf)
+    }
+}
+
+/// Lets codecs use sockets directly, without a compatibility wrapper
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for Socket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncRead::poll_read(self, cx, buf)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for Socket {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_close(self, cx)
+    }
+}
+
+/// The reading half of a `Socket`, made by `Socket::split`
+pub struct ReadHalf {
+    socket: Rc<Socket>,
+}
+
+/// The writing half of a `Socket`, made by `Socket::split`
+pub struct WriteHalf {
+    socket: Rc<Socket>,
+}
+
+impl AsyncRead for ReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.socket.raw_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for WriteHalf {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.socket.raw_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.socket.raw_flush(cx)
+    }
+
+    /// Shut down the write side. The read half keeps receiving until the peer closes.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.socket.raw_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for ReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncRead::poll_read(self, cx, buf)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for WriteHalf {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_close(self, cx)
     }
 }
 
@@ -102,17 +253,16 @@ impl Drop for SocketListener {
 
 impl SocketListener {
     pub fn new(port: u16) -> io::Result<Self> {
-        unsafe { listener_create(port) }
-            .errorkind()
+        error::result(unsafe { listener_create(port) }.handle(), NO_HANDLE)
             .map(|handle| Self { handle })
-            .map_err(io::Error::from)
     }
 }
 
 impl Stream for SocketListener {
     type Item = io::Result<Socket>;
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        let ret = unsafe { listen(self.handle) };
-        poll_ffi(ret, self.handle, cx).map(|v| Some(v.map(|handle| Socket { handle })))
+        let ret = unsafe { listen(self.handle) }.handle();
+        poll_ffi(ret, self.handle, Interest::Acceptable, cx)
+            .map(|v| Some(v.map(|handle| Socket { handle })))
     }
 }
@@ -1,21 +1,134 @@
-use futures::executor::LocalPool;
+use crate::debug;
+use crate::runtime::Runtime;
+use futures::channel::oneshot;
+use futures::future::{AbortHandle, Abortable, FusedFuture, FutureExt};
 use futures::task::LocalSpawnExt;
-use once_cell::unsync::Lazy;
+use std::fmt;
 use std::future::Future;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-pub static mut TASK_POOL: Lazy<LocalPool> = Lazy::new(LocalPool::new);
-
+/// Run tasks until they stall, returning the number of tasks left. The host stops the module
+/// once there are none.
 #[no_mangle]
-pub unsafe extern "C" fn run_tasks() {
-    TASK_POOL.run_until_stalled();
+pub extern "C" fn run_tasks() -> u32 {
+    Runtime::with(|rt| {
+        rt.pool.borrow_mut().run_until_stalled();
+        rt.tasks.get()
+    })
+}
+
+/// Spawn a task. It keeps running if the returned handle is dropped, unless the handle was made
+/// with `abort_on_drop`.
+pub fn spawn<F: Future + 'static>(f: F) -> JoinHandle<F::Output> {
+    let (abort, registration) = AbortHandle::new_pair();
+    let (tx, rx) = oneshot::channel();
+    let task = Abortable::new(AssertUnwindSafe(f).catch_unwind(), registration);
+    Runtime::with(|rt| {
+        rt.tasks.set(rt.tasks.get() + 1);
+        rt.spawner
+            .spawn_local(async move {
+                let result = match task.await {
+                    Ok(Ok(output)) => Ok(output),
+                    Ok(Err(_)) => Err(JoinError::Panicked),
+                    Err(_) => Err(JoinError::Cancelled),
+                };
+                // Nobody may be waiting for the result
+                let _ = tx.send(result);
+                Runtime::with(|rt| rt.tasks.set(rt.tasks.get() - 1));
+            })
+            .unwrap();
+    });
+    JoinHandle {
+        rx,
+        abort,
+        abort_on_drop: false,
+        done: false,
+    }
+}
+
+/// Why a task did not finish
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted
+    Cancelled,
+    /// The task panicked. Only reported natively; in wasm a panic stops the whole module.
+    Panicked,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => write!(f, "task was cancelled"),
+            JoinError::Panicked => write!(f, "task panicked"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+/// Waits for a spawned task to finish, and can abort it
+pub struct JoinHandle<T> {
+    rx: oneshot::Receiver<Result<T, JoinError>>,
+    abort: AbortHandle,
+    abort_on_drop: bool,
+    done: bool,
 }
 
-pub fn spawn<F: Future<Output = ()> + 'static>(f: F) {
-    unsafe {
-        TASK_POOL.spawner().spawn_local(f).unwrap();
+impl<T> JoinHandle<T> {
+    /// Stop the task the next time it would be polled
+    pub fn abort(&self) {
+        self.abort.abort()
+    }
+
+    /// Abort the task when this handle is dropped
+    pub fn abort_on_drop(mut self) -> Self {
+        self.abort_on_drop = true;
+        self
     }
+
+    pub(crate) fn abort_handle(&self) -> AbortHandle {
+        self.abort.clone()
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if self.abort_on_drop {
+            self.abort();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let poll = self.rx.poll_unpin(cx).map(|result| {
+            // The task is only dropped without sending if the runtime goes away
+            result.unwrap_or(Err(JoinError::Cancelled))
+        });
+        if poll.is_ready() {
+            self.done = true;
+        }
+        poll
+    }
+}
+
+impl<T> FusedFuture for JoinHandle<T> {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+/// Entry point used by `#[libplugin::main]`: report panics through `debug`, then spawn `main`
+#[doc(hidden)]
+pub fn start<F: Future<Output = ()> + 'static>(main: F) {
+    std::panic::set_hook(Box::new(|info| {
+        debug(info.to_string());
+    }));
+    spawn(main);
 }
 
 // Credit: async-std authors
@@ -0,0 +1,122 @@
+use crate::imports::{close, timer_create, timer_poll};
+use crate::reactor;
+use futures::future::FusedFuture;
+use protocols::{Handle, Interest};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// Completes once its duration has passed. Timers are kept by the host and wake the module like
+/// sockets do.
+pub struct Sleep {
+    handle: Option<Handle>,
+}
+
+/// Wait for `duration`
+pub fn sleep(duration: Duration) -> Sleep {
+    let ms = duration.as_millis().min(u32::MAX as u128) as u32;
+    let handle = unsafe { timer_create(ms) }
+        .handle()
+        .expect("The host has no timers");
+    Sleep {
+        handle: Some(handle),
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let handle = match self.handle {
+            Some(handle) => handle,
+            None => return Poll::Ready(()),
+        };
+        match unsafe { timer_poll(handle) }.into_poll() {
+            Poll::Pending => {
+                reactor::register(handle, Interest::Readable, cx.waker().clone());
+                Poll::Pending
+            }
+            // The timer is gone either way
+            Poll::Ready(_) => {
+                unsafe { close(handle) };
+                self.handle = None;
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+impl FusedFuture for Sleep {
+    fn is_terminated(&self) -> bool {
+        self.handle.is_none()
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle {
+            unsafe { close(handle) }
+        }
+    }
+}
+
+/// The error returned when a `timeout` runs out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "timed out")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+impl From<TimedOut> for io::Error {
+    fn from(_: TimedOut) -> Self {
+        io::Error::from(io::ErrorKind::TimedOut)
+    }
+}
+
+/// Run `future`, giving up once `duration` has passed
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+        done: false,
+    }
+}
+
+/// Future returned by `timeout`
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+    done: bool,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, TimedOut>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // Safety: `future` is never moved out of, and the other fields are not pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        let poll = match future.poll(cx) {
+            Poll::Ready(output) => Poll::Ready(Ok(output)),
+            Poll::Pending => Pin::new(&mut this.sleep).poll(cx).map(|()| Err(TimedOut)),
+        };
+        if poll.is_ready() {
+            this.done = true;
+        }
+        poll
+    }
+}
+
+impl<F: Future> FusedFuture for Timeout<F> {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
@@ -0,0 +1,24 @@
+#![cfg(not(target_arch = "wasm32"))]
+use libplugin::{abi_features, abi_version};
+use protocols::abi;
+
+#[test]
+fn exports_the_enabled_features() {
+    let mut expected = 0;
+    if cfg!(feature = "timers") {
+        expected |= abi::TIMERS;
+    }
+    if cfg!(feature = "random") {
+        expected |= abi::RANDOM;
+    }
+    if cfg!(feature = "peer-addr") {
+        expected |= abi::PEER_ADDR;
+    }
+    if cfg!(feature = "error-messages") {
+        expected |= abi::ERROR_MESSAGES;
+    }
+    let (version, features) = (abi_version(), abi_features());
+    assert_eq!(version, abi::VERSION);
+    assert_eq!(features, expected);
+    assert_eq!(abi::check(version, features), Ok(()));
+}
@@ -0,0 +1,149 @@
+#![cfg(not(target_arch = "wasm32"))]
+use futures::StreamExt;
+use libplugin::{mock, spawn, Addr, AsyncReadExt, AsyncWriteExt, Socket, SocketListener};
+
+async fn echo_server() {
+    let mut listener = SocketListener::new(5062).unwrap();
+    while let Some(Ok(mut socket)) = listener.next().await {
+        spawn(async move {
+            let mut buf = [0u8; 64];
+            loop {
+                match socket.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => socket.write_all(&buf[..n]).await.unwrap(),
+                }
+            }
+        });
+    }
+}
+
+#[test]
+fn echo() {
+    spawn(echo_server());
+    let reply = mock::block_on(async {
+        let mut conn = mock::connect(5062).await.unwrap();
+        conn.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        conn.read_exact(&mut buf).await.unwrap();
+        buf
+    });
+    assert_eq!(&reply, b"hello");
+    assert_eq!(mock::metrics().bytes_written, 5);
+}
+
+#[test]
+fn module_connects_out() {
+    spawn(async {
+        let mut socket = Socket::connect("peer", 7).unwrap().await.unwrap();
+        socket.write_all(b"ping").await.unwrap();
+        socket.close().await.unwrap();
+    });
+    let received = mock::block_on(async {
+        let mut listener = mock::listen("peer", 7).await;
+        let mut conn = listener.next().await.unwrap();
+        let mut received = Vec::new();
+        conn.read_to_end(&mut received).await.unwrap();
+        received
+    });
+    assert_eq!(received, b"ping");
+}
+
+#[test]
+#[should_panic(expected = "Deadlock")]
+fn deadlock() {
+    spawn(echo_server());
+    mock::block_on(async {
+        let mut conn = mock::connect(5062).await.unwrap();
+        // Nothing was sent, so nothing comes back
+        let mut buf = [0u8; 1];
+        conn.read_exact(&mut buf).await.unwrap();
+    });
+}
+
+#[test]
+fn split_halves_wake_separately() {
+    let data = vec![7u8; 1 << 16];
+    let sent = data.clone();
+    let reader = spawn(async move {
+        let socket = Socket::connect("peer", 9).unwrap().await.unwrap();
+        let (mut read, mut write) = socket.split();
+        // Blocks on a full buffer while the reader waits on the same handle
+        spawn(async move {
+            write.write_all(&sent).await.unwrap();
+            write.close().await.unwrap();
+        });
+        let mut buf = [0u8; 1];
+        read.read_exact(&mut buf).await.unwrap();
+        buf
+    });
+    let received = mock::block_on(async {
+        let mut listener = mock::listen("peer", 9).await;
+        let mut conn = listener.next().await.unwrap();
+        let mut received = Vec::new();
+        conn.read_to_end(&mut received).await.unwrap();
+        conn.write_all(b"!").await.unwrap();
+        conn.flush().await.unwrap();
+        received
+    });
+    assert_eq!(received, data);
+    assert_eq!(&mock::block_on(reader).unwrap(), b"!");
+}
+
+#[test]
+fn peer_identity() {
+    spawn(async {
+        let mut listener = SocketListener::new(5063).unwrap();
+        while let Some(Ok(mut socket)) = listener.next().await {
+            let greeting = format!("hello {}", socket.peer_module().unwrap());
+            socket.write_all(greeting.as_bytes()).await.unwrap();
+            socket.close().await.unwrap();
+        }
+    });
+    let greeting = mock::block_on(async {
+        let mut conn = mock::connect_as("client", 5063).await.unwrap();
+        let mut greeting = String::new();
+        conn.read_to_string(&mut greeting).await.unwrap();
+        greeting
+    });
+    assert_eq!(greeting, "hello client");
+}
+
+#[test]
+fn connecting_socket_addresses() {
+    let addrs = spawn(async {
+        let socket = Socket::connect("peer", 11).unwrap().await.unwrap();
+        (socket.local_addr().unwrap(), socket.peer_addr().unwrap())
+    });
+    mock::block_on(async {
+        let mut listener = mock::listen("peer", 11).await;
+        listener.next().await.unwrap()
+    });
+    let (local, peer) = mock::block_on(addrs).unwrap();
+    assert_eq!(local.module, mock::MODULE);
+    assert!(local.port >= 49152);
+    assert_eq!(
+        peer,
+        Addr {
+            module: "peer".into(),
+            port: 11
+        }
+    );
+}
+
+#[test]
+fn errors_carry_the_host_message() {
+    let error = spawn(async {
+        let mut socket = Socket::connect("peer", 13).unwrap().await.unwrap();
+        let mut buf = [0u8; 1];
+        socket.read(&mut buf).await.unwrap_err()
+    });
+    mock::block_on(async {
+        let mut listener = mock::listen("peer", 13).await;
+        // Hang up without shutting down, resetting the connection
+        drop(listener.next().await.unwrap());
+    });
+    let error = mock::block_on(error).unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+    // Described by the host, rather than made up from the error code
+    assert!(error.get_ref().is_some());
+}
@@ -0,0 +1,59 @@
+#![cfg(not(target_arch = "wasm32"))]
+use futures::StreamExt;
+use libplugin::{mock, random, spawn, AsyncReadExt, AsyncWriteExt, SocketListener};
+
+#[test]
+fn seeded_randomness_repeats() {
+    mock::seed(42);
+    let first = (random::u64(), random::u32());
+    mock::seed(42);
+    assert_eq!((random::u64(), random::u32()), first);
+    mock::seed(43);
+    assert_ne!(random::u64(), first.0);
+}
+
+#[test]
+fn fill() {
+    let mut buf = [0u8; 64];
+    random::fill(&mut buf).unwrap();
+    assert!(buf.iter().any(|&b| b != 0));
+}
+
+// What getrandom calls on wasm, where modules have no other source
+extern "Rust" {
+    fn __getrandom_custom(dest: *mut u8, len: usize) -> u32;
+}
+
+#[test]
+fn getrandom_uses_the_host() {
+    mock::seed(7);
+    let mut expected = [0u8; 32];
+    random::fill(&mut expected).unwrap();
+
+    mock::seed(7);
+    let mut buf = [0u8; 32];
+    assert_eq!(
+        unsafe { __getrandom_custom(buf.as_mut_ptr(), buf.len()) },
+        0
+    );
+    assert_eq!(buf, expected);
+}
+
+#[test]
+fn seeding_keeps_open_sockets() {
+    spawn(async {
+        let mut listener = SocketListener::new(5070).unwrap();
+        let mut socket = listener.next().await.unwrap().unwrap();
+        socket.write_all(b"still here").await.unwrap();
+    });
+    mock::run_until_stalled();
+
+    mock::seed(3);
+    let reply = mock::block_on(async {
+        let mut conn = mock::connect(5070).await.unwrap();
+        let mut buf = [0u8; 10];
+        conn.read_exact(&mut buf).await.unwrap();
+        buf
+    });
+    assert_eq!(&reply, b"still here");
+}
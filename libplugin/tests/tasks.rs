@@ -0,0 +1,80 @@
+#![cfg(not(target_arch = "wasm32"))]
+use futures::future::{self, Either};
+use libplugin::{mock, sleep, spawn, timeout, JoinError, Nursery, TimedOut};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+#[test]
+fn join_handle_returns_output() {
+    let handle = spawn(async { 6 * 7 });
+    assert_eq!(mock::block_on(handle), Ok(42));
+}
+
+#[test]
+fn abort() {
+    let handle = spawn(future::pending::<()>());
+    handle.abort();
+    assert_eq!(mock::block_on(handle), Err(JoinError::Cancelled));
+}
+
+#[test]
+fn abort_on_drop() {
+    let ran = Rc::new(Cell::new(false));
+    let flag = ran.clone();
+    drop(spawn(async move { flag.set(true) }).abort_on_drop());
+    let detached = spawn(async {});
+    mock::block_on(detached).unwrap();
+    assert!(!ran.get());
+}
+
+#[test]
+fn panic_is_reported() {
+    let handle = spawn(async { panic!("oh no") });
+    assert_eq!(mock::block_on(handle), Err(JoinError::Panicked));
+    // Other tasks carry on
+    assert_eq!(mock::block_on(spawn(async { 1 })), Ok(1));
+}
+
+#[test]
+fn nursery_cancels_children() {
+    let mut nursery = Nursery::new();
+    let a = nursery.spawn(future::pending::<()>());
+    let b = nursery.spawn(future::pending::<()>());
+    drop(nursery);
+    assert_eq!(mock::block_on(a), Err(JoinError::Cancelled));
+    assert_eq!(mock::block_on(b), Err(JoinError::Cancelled));
+}
+
+#[test]
+fn nursery_join() {
+    let count = Rc::new(Cell::new(0));
+    let mut nursery = Nursery::new();
+    for _ in 0..3 {
+        let count = count.clone();
+        nursery.spawn(async move { count.set(count.get() + 1) });
+    }
+    mock::block_on(nursery.join());
+    assert_eq!(count.get(), 3);
+}
+
+#[test]
+fn sleep_and_timeout() {
+    let start = Instant::now();
+    let slow = timeout(Duration::from_millis(20), future::pending::<()>());
+    assert_eq!(mock::block_on(spawn(slow)), Ok(Err(TimedOut)));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    let fast = timeout(Duration::from_secs(60), async { 1 });
+    assert_eq!(mock::block_on(spawn(fast)), Ok(Ok(1)));
+
+    let race = async {
+        let short = sleep(Duration::from_millis(5));
+        let long = sleep(Duration::from_secs(60));
+        match future::select(short, long).await {
+            Either::Left(_) => "short",
+            Either::Right(_) => "long",
+        }
+    };
+    assert_eq!(mock::block_on(spawn(race)), Ok("short"));
+}
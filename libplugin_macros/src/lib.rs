@@ -0,0 +1,72 @@
+//! Procedural macros for libplugin. Use them through `libplugin`, not directly.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Error, ItemFn, ReturnType};
+
+/// Mark the module's entry point. The function must be `async` and take no arguments. It is
+/// exported as `main`, and spawned once a panic hook reporting through `debug` is installed.
+/// Outside wasm, `main` is an ordinary function, so tests can start the module with
+/// `libplugin::mock`.
+///
+/// ```ignore
+/// #[libplugin::main]
+/// async fn main() {
+///     libplugin::debug("Hello!");
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
+    let function = parse_macro_input!(item as ItemFn);
+    match expand(args.into(), function) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(
+    args: proc_macro2::TokenStream,
+    function: ItemFn,
+) -> syn::Result<proc_macro2::TokenStream> {
+    if !args.is_empty() {
+        return Err(Error::new(
+            args.span(),
+            "#[libplugin::main] takes no arguments",
+        ));
+    }
+    let sig = &function.sig;
+    if sig.asyncness.is_none() {
+        return Err(Error::new(
+            sig.fn_token.span(),
+            "the main function must be async",
+        ));
+    }
+    if !sig.inputs.is_empty() {
+        return Err(Error::new(
+            sig.inputs.span(),
+            "the main function takes no arguments",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(Error::new(
+            sig.generics.span(),
+            "the main function cannot be generic",
+        ));
+    }
+    if let ReturnType::Type(_, ty) = &sig.output {
+        return Err(Error::new(ty.span(), "the main function must return ()"));
+    }
+
+    let attrs = &function.attrs;
+    let body = &function.block;
+    Ok(quote! {
+        // Natively, the module is called directly by tests, which have a `main` of their own
+        #[cfg_attr(target_arch = "wasm32", no_mangle)]
+        pub extern "C" fn main() {
+            #(#attrs)*
+            async fn main() #body
+            ::libplugin::start(main());
+        }
+    })
+}
@@ -1,15 +1,18 @@
-use criterion::{criterion_group, criterion_main, Criterion, Bencher};
+use criterion::{criterion_group, criterion_main, Bencher, Criterion};
 use futures::executor::LocalPool;
 use futures::task::SpawnExt;
 use futures::{AsyncReadExt, AsyncWriteExt};
-use loopback::Loopback;
+use loopback::{Loopback, LoopbackConfig};
 
 const BUF_SIZE: usize = 8192;
+/// About the size of a renderer transform update
+const SMALL_MESSAGE: usize = 32;
+
 async fn receiver_first_task(mut lb: Loopback, n: usize) {
     let mut buf = [8u8; BUF_SIZE];
     for _ in 0..n {
-        lb.read(&mut buf).await.unwrap();
-        lb.write(&buf).await.unwrap();
+        lb.read_exact(&mut buf).await.unwrap();
+        lb.write_all(&buf).await.unwrap();
         lb.flush().await.unwrap();
     }
 }
@@ -17,9 +20,9 @@ async fn receiver_first_task(mut lb: Loopback, n: usize) {
 async fn sender_first_task(mut lb: Loopback, n: usize) {
     let mut buf = [8u8; BUF_SIZE];
     for _ in 0..n {
-        lb.write(&buf).await.unwrap();
+        lb.write_all(&buf).await.unwrap();
         lb.flush().await.unwrap();
-        lb.read(&mut buf).await.unwrap();
+        lb.read_exact(&mut buf).await.unwrap();
     }
 }
 
@@ -34,10 +37,66 @@ fn echo(n: usize, bencher: &mut Bencher) {
     });
 }
 
+/// Send `n` small messages, flushing after each as a request/response protocol would
+async fn small_sender_task(mut lb: Loopback, n: usize) {
+    let message = [8u8; SMALL_MESSAGE];
+    for _ in 0..n {
+        lb.write_all(&message).await.unwrap();
+        lb.flush().await.unwrap();
+    }
+}
+
+/// Send `n` small messages, flushing only at the end as a stream of updates would
+async fn small_stream_task(mut lb: Loopback, n: usize) {
+    let message = [8u8; SMALL_MESSAGE];
+    for _ in 0..n {
+        lb.write_all(&message).await.unwrap();
+    }
+    lb.flush().await.unwrap();
+}
+
+async fn small_receiver_task(mut lb: Loopback, n: usize) {
+    let mut buf = [0u8; BUF_SIZE];
+    let mut left = n * SMALL_MESSAGE;
+    while left > 0 {
+        left -= lb.read(&mut buf).await.unwrap();
+    }
+}
+
+fn small_messages(n: usize, flush_each: bool, config: LoopbackConfig, bencher: &mut Bencher) {
+    let mut pool = LocalPool::new();
+    let spawner = pool.spawner();
+    bencher.iter(|| {
+        let (a, b) = Loopback::pair_with(config);
+        if flush_each {
+            spawner.spawn(small_sender_task(a, n)).unwrap();
+        } else {
+            spawner.spawn(small_stream_task(a, n)).unwrap();
+        }
+        spawner.spawn(small_receiver_task(b, n)).unwrap();
+        pool.run();
+    });
+}
+
 pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("echo 200", |b| echo(200, b));
     c.bench_function("echo 90", |b| echo(90, b));
     c.bench_function("echo 30", |b| echo(30, b));
+
+    let unbuffered = LoopbackConfig::default();
+    let buffered = LoopbackConfig {
+        flush_threshold: 4096,
+        ..Default::default()
+    };
+    c.bench_function("small 2000 flushed", |b| {
+        small_messages(2000, true, unbuffered, b)
+    });
+    c.bench_function("small 2000 streamed", |b| {
+        small_messages(2000, false, unbuffered, b)
+    });
+    c.bench_function("small 2000 streamed buffered", |b| {
+        small_messages(2000, false, buffered, b)
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,319 @@
+use crate::{Loopback, Stats, Transport};
+use futures::future::FutureExt;
+use futures::io::{AsyncRead, AsyncWrite, Error, Result};
+use futures_timer::Delay;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Most bytes held in transit. The rest wait in the ring, so the writer still sees backpressure.
+const MAX_IN_TRANSIT: usize = 16384;
+
+/// Bad conditions to simulate on a connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct Faults {
+    /// Seed for the random choices; connections made in the same order behave the same way
+    pub seed: u64,
+    /// Time data spends in transit before it can be read, counted from when it is first seen by
+    /// a read or a readiness check
+    pub latency: Duration,
+    /// Split writes into random chunks of at most this many bytes
+    pub max_chunk: Option<usize>,
+    /// Limit writes to this many bytes per second
+    pub bandwidth: Option<u64>,
+    /// Start failing with `NotConnected` once this many bytes have been read and written
+    pub fail_after: Option<u64>,
+    /// Chance of failing on each read or write after `fail_after` bytes
+    pub fail_chance: f64,
+    /// Chance of stalling on each read or write
+    pub stall_chance: f64,
+    /// Time to stall for
+    pub stall: Duration,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            latency: Duration::from_secs(0),
+            max_chunk: None,
+            bandwidth: None,
+            fail_after: None,
+            fail_chance: 1.0,
+            stall_chance: 0.0,
+            stall: Duration::from_secs(0),
+        }
+    }
+}
+
+/// A loopback socket which misbehaves as described by its `Faults`, for testing how modules cope
+/// with bad conditions.
+pub struct FaultyLoopback {
+    inner: Loopback,
+    faults: Faults,
+    rng: SmallRng,
+    /// Data read from `inner` which is still in transit, in the first `held_len` bytes
+    held: Box<[u8; MAX_IN_TRANSIT]>,
+    held_len: usize,
+    /// When each run of bytes in `held` may be read, oldest first, and how long it is
+    arrivals: VecDeque<(Instant, usize)>,
+    /// Wakes the reader when the oldest data in transit arrives
+    transit: Option<Delay>,
+    read_delay: Option<Delay>,
+    write_delay: Option<Delay>,
+    transferred: u64,
+    failed: bool,
+}
+
+impl FaultyLoopback {
+    /// Wrap `inner`. Each `stream` number gets different random choices from the same seed.
+    pub fn new(inner: Loopback, mut faults: Faults, stream: u64) -> Self {
+        faults.fail_chance = faults.fail_chance.clamp(0.0, 1.0);
+        faults.stall_chance = faults.stall_chance.clamp(0.0, 1.0);
+        let rng = SmallRng::seed_from_u64(faults.seed ^ stream.rotate_left(32));
+        Self {
+            inner,
+            faults,
+            rng,
+            held: Box::new([0; MAX_IN_TRANSIT]),
+            held_len: 0,
+            arrivals: VecDeque::new(),
+            transit: None,
+            read_delay: None,
+            write_delay: None,
+            transferred: 0,
+            failed: false,
+        }
+    }
+
+    /// Count transferred bytes, and decide whether the connection fails now
+    fn transfer(&mut self, n: usize) -> Result<()> {
+        self.transferred += n as u64;
+        match self.faults.fail_after {
+            Some(limit)
+                if self.transferred >= limit && self.rng.gen_bool(self.faults.fail_chance) =>
+            {
+                self.failed = true;
+                self.inner.reset();
+            }
+            _ => (),
+        }
+        self.check_failed()
+    }
+
+    fn check_failed(&self) -> Result<()> {
+        if self.failed {
+            Err(Error::from(io::ErrorKind::NotConnected))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Take whatever the peer has sent into `held`, so its time in transit starts now rather than
+    /// whenever the reader gets round to it. Returns the read from `inner`.
+    fn receive(&mut self, cx: &mut Context) -> Poll<Result<usize>> {
+        let start = self.held_len;
+        if start >= MAX_IN_TRANSIT {
+            return Poll::Pending;
+        }
+        let poll = Pin::new(&mut self.inner).poll_read(cx, &mut self.held[start..]);
+        let n = match poll {
+            Poll::Ready(Ok(n)) => n,
+            _ => 0,
+        };
+        self.held_len += n;
+        if n > 0 {
+            self.arrivals
+                .push_back((Instant::now() + self.faults.latency, n));
+            self.transfer(n)?;
+        }
+        poll
+    }
+
+    /// Bytes in transit which may be read now. Otherwise sets a timer to wake the reader once the
+    /// oldest of them arrives.
+    fn poll_arrived(&mut self, cx: &mut Context) -> Poll<usize> {
+        loop {
+            let now = Instant::now();
+            let ready = self
+                .arrivals
+                .iter()
+                .take_while(|(deadline, _)| *deadline <= now)
+                .map(|(_, n)| n)
+                .sum();
+            if ready > 0 {
+                return Poll::Ready(ready);
+            }
+            let wait = match self.arrivals.front() {
+                Some((deadline, _)) => deadline.saturating_duration_since(now),
+                None => return Poll::Pending,
+            };
+            let timer = self.transit.get_or_insert_with(|| Delay::new(wait));
+            if timer.poll_unpin(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.transit = None;
+        }
+    }
+
+    /// Hand over up to `buf.len()` of the `ready` bytes which have arrived
+    fn deliver(&mut self, buf: &mut [u8], ready: usize) -> usize {
+        let n = buf.len().min(ready);
+        buf[..n].copy_from_slice(&self.held[..n]);
+        self.held.copy_within(n..self.held_len, 0);
+        self.held_len -= n;
+        let mut left = n;
+        while left > 0 {
+            let (_, len) = self
+                .arrivals
+                .front_mut()
+                .expect("Delivered bytes not in transit");
+            if *len > left {
+                *len -= left;
+                break;
+            }
+            left -= *len;
+            self.arrivals.pop_front();
+            self.transit = None;
+        }
+        n
+    }
+
+    /// Maybe start stalling
+    fn stall(&mut self) -> Option<Delay> {
+        if self.faults.stall_chance > 0.0 && self.rng.gen_bool(self.faults.stall_chance) {
+            Some(Delay::new(self.faults.stall))
+        } else {
+            None
+        }
+    }
+}
+
+/// Poll a delay, clearing it once it has passed. Returns true if there was one.
+fn poll_delay(delay: &mut Option<Delay>, cx: &mut Context) -> Poll<bool> {
+    match delay {
+        Some(timer) => match timer.poll_unpin(cx) {
+            Poll::Ready(()) => {
+                *delay = None;
+                Poll::Ready(true)
+            }
+            Poll::Pending => Poll::Pending,
+        },
+        None => Poll::Ready(false),
+    }
+}
+
+impl Transport for FaultyLoopback {
+    fn is_readable(&mut self, cx: &mut Context) -> bool {
+        if self.failed {
+            return true;
+        }
+        let received = self.receive(cx);
+        if poll_delay(&mut self.read_delay, cx).is_pending() {
+            return false;
+        }
+        if self.arrivals.is_empty() {
+            // End of file or an error
+            received.is_ready()
+        } else {
+            self.poll_arrived(cx).is_ready()
+        }
+    }
+
+    fn is_writable(&mut self, cx: &mut Context) -> bool {
+        if self.failed {
+            return true;
+        }
+        poll_delay(&mut self.write_delay, cx).is_ready() && self.inner.is_writable(cx)
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown()
+    }
+
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl AsyncWrite for FaultyLoopback {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = &mut *self;
+        this.check_failed()?;
+        match poll_delay(&mut this.write_delay, cx) {
+            Poll::Pending => return Poll::Pending,
+            // Carry on from the stall or throttle that just ended
+            Poll::Ready(true) => (),
+            Poll::Ready(false) => {
+                if let Some(stall) = this.stall() {
+                    this.write_delay = Some(stall);
+                    return self.poll_write(cx, buf);
+                }
+            }
+        }
+
+        let len = match this.faults.max_chunk {
+            Some(max) if !buf.is_empty() => buf.len().min(this.rng.gen_range(1..=max.max(1))),
+            _ => buf.len(),
+        };
+        let n = match Pin::new(&mut this.inner).poll_write(cx, &buf[..len]) {
+            Poll::Ready(Ok(n)) => n,
+            other => return other,
+        };
+
+        if let Some(bandwidth) = this.faults.bandwidth {
+            let time = Duration::from_secs_f64(n as f64 / bandwidth.max(1) as f64);
+            this.write_delay = Some(Delay::new(time));
+        }
+        this.transfer(n)?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        self.check_failed()?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        self.check_failed()?;
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl AsyncRead for FaultyLoopback {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let this = &mut *self;
+        this.check_failed()?;
+        if this.arrivals.is_empty() {
+            match poll_delay(&mut this.read_delay, cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(true) => (),
+                Poll::Ready(false) => {
+                    if let Some(stall) = this.stall() {
+                        this.read_delay = Some(stall);
+                        return self.poll_read(cx, buf);
+                    }
+                }
+            }
+        }
+
+        let received = this.receive(cx);
+        if this.arrivals.is_empty() {
+            return received;
+        }
+
+        // Hand over the data once it has been in transit for long enough
+        match this.poll_arrived(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(ready) => Poll::Ready(Ok(this.deliver(buf, ready))),
+        }
+    }
+}
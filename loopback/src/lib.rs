@@ -1,87 +1,280 @@
-use futures::channel::mpsc::{channel, Receiver, Sender};
 use futures::io::{AsyncRead, AsyncWrite, Error, Result};
-use futures::sink::Sink;
-use futures::stream::{Peekable, StreamExt};
+use ring::Ring;
 use std::io;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
-const CHANNEL_CAP: usize = 32;
-const MAX_BUFFER_SIZE: usize = 16384; // 16k is probably about the biggest message we want to have.
+mod faulty;
+mod ring;
+#[cfg(feature = "tokio")]
+mod tokio_io;
+#[cfg(feature = "typed")]
+pub mod typed;
+pub use faulty::{Faults, FaultyLoopback};
 
-/// An asynchronous loop back socket
+/// Bytes buffered in each direction by default. Small enough for both rings to stay in cache.
+const RING_CAPACITY: usize = 16384;
+
+/// Settings for a pair of loopback sockets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoopbackConfig {
+    /// Bytes buffered in each direction, rounded up to a power of two
+    pub capacity: usize,
+    /// Writes are held back until this many bytes are pending or the socket is flushed, so the
+    /// peer isn't woken for every small message. Zero sends every write straight away.
+    pub flush_threshold: usize,
+}
+
+impl Default for LoopbackConfig {
+    fn default() -> Self {
+        Self {
+            capacity: RING_CAPACITY,
+            flush_threshold: 0,
+        }
+    }
+}
+
+/// A byte stream between two modules. With the `tokio` feature, `Connection` and the loopback
+/// types also implement tokio's `AsyncRead` and `AsyncWrite`.
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin {
+    /// Returns true if a read would not block.
+    fn is_readable(&mut self, cx: &mut Context) -> bool;
+    /// Returns true if a write would not block.
+    fn is_writable(&mut self, cx: &mut Context) -> bool;
+    /// Returns true if this connection is ready for a read or a write.
+    fn has_data(&mut self, cx: &mut Context) -> bool {
+        self.is_readable(cx) || self.is_writable(cx)
+    }
+    /// Shut down the write side. The peer reads end of file once it has read everything sent.
+    /// Writes which don't fit yet are kept, and sent by `poll_close`, which finishes the
+    /// shutdown.
+    fn shutdown(&mut self);
+    /// Counters for this end of the connection
+    fn stats(&self) -> Stats;
+}
+
+/// A connection handed out by the match maker
+pub type Connection = Box<dyn Transport>;
+
+/// Counters kept by each loopback socket
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub bytes_written: u64,
+    pub bytes_read: u64,
+    /// Successful writes
+    pub messages_written: u64,
+    /// Successful reads, not counting end of file
+    pub messages_read: u64,
+    /// Times a write had to wait for the peer to make room
+    pub blocked_on_full: u64,
+    /// Most bytes ever waiting to be read by the peer
+    pub high_water: usize,
+}
+
+/// An asynchronous loop back socket.
+///
+/// Closing a loopback only shuts down its write side: the peer reads the remaining data and then
+/// end of file, and may still send a reply. Dropping a loopback without closing it resets the
+/// connection, and the peer's reads fail with `ConnectionReset` once buffered data is drained.
+/// Writes fail with `BrokenPipe` once either side has shut down the direction written to.
 pub struct Loopback {
-    tx: Sender<Vec<u8>>,
+    tx: Arc<Ring>,
+    rx: Arc<Ring>,
+    /// Writes held back until `flush_threshold` is reached
     tx_buf: Vec<u8>,
-
-    rx: Peekable<Receiver<Vec<u8>>>,
-    rx_buf: Vec<u8>,
+    flush_threshold: usize,
+    /// Shut down, though the write side stays open until `tx_buf` has drained
+    closing: bool,
+    stats: Stats,
 }
 
 impl Loopback {
     /// Create a pair of connected loopback sockets.
     pub fn pair() -> (Self, Self) {
-        let (a_tx, b_rx) = channel(CHANNEL_CAP);
-        let (b_tx, a_rx) = channel(CHANNEL_CAP);
+        Self::pair_with(LoopbackConfig::default())
+    }
+
+    /// Create a pair of connected loopback sockets with the given settings.
+    pub fn pair_with(config: LoopbackConfig) -> (Self, Self) {
+        let a_to_b = Arc::new(Ring::new(config.capacity));
+        let b_to_a = Arc::new(Ring::new(config.capacity));
         (
-            Loopback::with_channels(a_tx, a_rx),
-            Loopback::with_channels(b_tx, b_rx),
+            Self::with_rings(a_to_b.clone(), b_to_a.clone(), &config),
+            Self::with_rings(b_to_a, a_to_b, &config),
         )
     }
 
+    fn with_rings(tx: Arc<Ring>, rx: Arc<Ring>, config: &LoopbackConfig) -> Self {
+        Self {
+            tx,
+            rx,
+            tx_buf: Vec::with_capacity(config.flush_threshold),
+            flush_threshold: config.flush_threshold,
+            closing: false,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Counters for this end of the connection
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Shut down the write side. The peer reads end of file once it has read everything sent.
+    /// Unflushed writes are sent first. If they don't all fit, the write side stays open until
+    /// `poll_close` has sent the rest.
+    pub fn shutdown(&mut self) {
+        self.closing = true;
+        if self.push() {
+            self.tx.close_write();
+        }
+    }
+
     /// Returns true if this loopback is ready for a read or a write.
     pub fn has_data(&mut self, cx: &mut Context) -> bool {
-        !self.rx_buf.is_empty()
-            || Pin::new(&mut self.rx).poll_peek(cx).is_ready()
-            || Pin::new(&mut self.tx).poll_ready(cx).is_ready()
+        self.is_readable(cx) || self.is_writable(cx)
     }
 
-    fn with_channels(tx: Sender<Vec<u8>>, rx: Receiver<Vec<u8>>) -> Self {
-        Self {
-            tx,
-            rx: rx.peekable(),
-            tx_buf: Vec::new(),
-            rx_buf: Vec::new(),
+    /// Returns true if there is data or end of file to read.
+    pub fn is_readable(&mut self, cx: &mut Context) -> bool {
+        self.rx.poll_readable(cx).is_ready()
+    }
+
+    /// Returns true if there is room to write, or the peer has gone away.
+    pub fn is_writable(&mut self, cx: &mut Context) -> bool {
+        self.tx.poll_writable(cx).is_ready()
+    }
+
+    /// Drop the connection abnormally, as if this end had gone away
+    fn reset(&mut self) {
+        self.tx.reset();
+        self.rx.close_read();
+    }
+
+    /// Move as much of `tx_buf` into the ring as fits. Returns true if it is empty afterwards.
+    fn push(&mut self) -> bool {
+        if let Some(n) = self.tx.write(&self.tx_buf) {
+            self.tx_buf.drain(..n);
+        }
+        self.tx_buf.is_empty()
+    }
+
+    /// Move all of `tx_buf` into the ring, waiting for room if necessary
+    fn poll_push(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        while !self.push() {
+            if self.tx.is_read_closed() {
+                return Poll::Ready(Err(Error::from(io::ErrorKind::BrokenPipe)));
+            }
+            if self.tx.poll_writable(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Write straight into the ring
+    fn poll_write_through(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize>> {
+        loop {
+            if let Some(n) = self.tx.write(buf) {
+                return Poll::Ready(Ok(n));
+            }
+            if self.tx.is_read_closed() {
+                return Poll::Ready(Err(Error::from(io::ErrorKind::BrokenPipe)));
+            }
+            if self.tx.poll_writable(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+
+    /// Hold back writes until `flush_threshold` bytes are pending
+    fn poll_write_buffered(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize>> {
+        if self.tx_buf.len() >= self.flush_threshold {
+            match self.poll_push(cx)? {
+                Poll::Ready(()) => (),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        let n = buf.len().min(self.flush_threshold - self.tx_buf.len());
+        self.tx_buf.extend_from_slice(&buf[..n]);
+        if self.tx_buf.len() >= self.flush_threshold {
+            self.push();
         }
+        Poll::Ready(Ok(n))
     }
 }
 
-fn ncerror<T>(_: T) -> io::Error {
-    io::Error::from(io::ErrorKind::NotConnected)
+impl Transport for Loopback {
+    fn is_readable(&mut self, cx: &mut Context) -> bool {
+        Loopback::is_readable(self, cx)
+    }
+
+    fn is_writable(&mut self, cx: &mut Context) -> bool {
+        Loopback::is_writable(self, cx)
+    }
+
+    fn shutdown(&mut self) {
+        Loopback::shutdown(self)
+    }
+
+    fn stats(&self) -> Stats {
+        Loopback::stats(self)
+    }
 }
 
-impl AsyncWrite for Loopback {
-    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize>> {
-        self.tx_buf.extend_from_slice(buf);
-        if self.tx_buf.len() > MAX_BUFFER_SIZE {
-            self.poll_flush(cx)?.map(|_| Ok(0))
-        } else {
-            Poll::Ready(Ok(buf.len()))
+impl Drop for Loopback {
+    fn drop(&mut self) {
+        if !self.tx.is_write_closed() {
+            self.tx.reset();
         }
+        self.rx.close_read();
     }
+}
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
-        if self.tx_buf.is_empty() {
-            return Poll::Ready(Ok(()));
+impl AsyncWrite for Loopback {
+    /// Copies as much of `buf` as fits into the peer's ring, returning `Pending` while it is full.
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if self.closing || self.tx.is_read_closed() || self.tx.is_write_closed() {
+            return Poll::Ready(Err(Error::from(io::ErrorKind::BrokenPipe)));
         }
 
-        if Pin::new(&mut self.tx)
-            .poll_ready(cx)
-            .map_err(ncerror)?
-            .is_pending()
-        {
-            return Poll::Pending;
+        let poll = if self.flush_threshold == 0 {
+            self.poll_write_through(cx, buf)
+        } else {
+            self.poll_write_buffered(cx, buf)
+        };
+
+        let queued = self.tx.len() + self.tx_buf.len();
+        let stats = &mut self.stats;
+        match poll {
+            Poll::Ready(Ok(n)) => {
+                stats.bytes_written += n as u64;
+                stats.messages_written += 1;
+                stats.high_water = stats.high_water.max(queued);
+            }
+            Poll::Pending => stats.blocked_on_full += 1,
+            Poll::Ready(Err(_)) => (),
         }
+        poll
+    }
 
-        let buf = std::mem::take(&mut self.tx_buf);
-        Pin::new(&mut self.tx).start_send(buf).map_err(ncerror)?;
-        Pin::new(&mut self.tx).poll_flush(cx).map_err(ncerror)
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        self.poll_push(cx)
     }
 
-    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<()>> {
-        self.tx.close_channel();
-        self.rx.get_mut().close();
-        Poll::Ready(Ok(()))
+    /// Flushes, then shuts down the write side only
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        self.closing = true;
+        let flushed = self.poll_push(cx);
+        if flushed.is_ready() {
+            self.tx.close_write();
+        }
+        flushed
     }
 }
 
@@ -91,20 +284,27 @@ impl AsyncRead for Loopback {
         cx: &mut Context,
         buf: &mut [u8],
     ) -> Poll<Result<usize>> {
-        if self.rx_buf.is_empty() {
-            match self.rx.poll_next_unpin(cx) {
-                Poll::Ready(Some(buf)) => self.rx_buf = buf,
-                Poll::Pending => return Poll::Pending,
-                Poll::Ready(None) => {
-                    return Poll::Ready(Err(Error::from(io::ErrorKind::NotConnected)))
-                }
-            }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
         }
 
-        let n = buf.len().min(self.rx_buf.len());
-        buf[..n].copy_from_slice(&self.rx_buf[..n]);
-        self.rx_buf.drain(..n);
-
-        Poll::Ready(Ok(n))
+        loop {
+            // Check before reading, so nothing written just before the peer closed is missed
+            let closed = self.rx.is_write_closed();
+            if let Some(n) = self.rx.read(buf) {
+                self.stats.bytes_read += n as u64;
+                self.stats.messages_read += 1;
+                return Poll::Ready(Ok(n));
+            }
+            if closed && self.rx.is_reset() {
+                return Poll::Ready(Err(Error::from(io::ErrorKind::ConnectionReset)));
+            }
+            if closed {
+                return Poll::Ready(Ok(0));
+            }
+            if self.rx.poll_readable(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
     }
 }
@@ -0,0 +1,168 @@
+use futures::task::AtomicWaker;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
+use std::task::{Context, Poll};
+
+/// A lock-free single producer, single consumer byte ring buffer. `head` and `tail` count the
+/// bytes ever read and written, so the buffer is empty when they are equal and full when they are
+/// `capacity` apart. Each side only wakes the other on an empty or full transition.
+pub struct Ring {
+    /// Owned allocation of `capacity` bytes, from a boxed slice
+    buf: *mut u8,
+    mask: usize,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    reader: AtomicWaker,
+    writer: AtomicWaker,
+    read_closed: AtomicBool,
+    write_closed: AtomicBool,
+    reset: AtomicBool,
+}
+
+// The reader and writer only ever touch disjoint regions of `buf`, handed over through `head`
+// and `tail`.
+unsafe impl Sync for Ring {}
+unsafe impl Send for Ring {}
+
+impl Ring {
+    /// Create a ring holding at least `capacity` bytes
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1).next_power_of_two();
+        Self {
+            buf: Box::into_raw(vec![0u8; capacity].into_boxed_slice()) as *mut u8,
+            mask: capacity - 1,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            reader: AtomicWaker::new(),
+            writer: AtomicWaker::new(),
+            read_closed: AtomicBool::new(false),
+            write_closed: AtomicBool::new(false),
+            reset: AtomicBool::new(false),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.mask + 1
+    }
+
+    /// Number of bytes waiting to be read
+    pub fn len(&self) -> usize {
+        self.tail.load(SeqCst).wrapping_sub(self.head.load(SeqCst))
+    }
+
+    /// Ready when there are bytes to read, or the writer has gone away. Must only be called
+    /// by the reader.
+    pub fn poll_readable(&self, cx: &mut Context) -> Poll<()> {
+        let ready = || self.len() > 0 || self.write_closed.load(SeqCst);
+        if ready() {
+            return Poll::Ready(());
+        }
+        self.reader.register(cx.waker());
+        if ready() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Ready when there is room to write, or the reader has gone away. Must only be called
+    /// by the writer.
+    pub fn poll_writable(&self, cx: &mut Context) -> Poll<()> {
+        let ready = || self.len() < self.capacity() || self.read_closed.load(SeqCst);
+        if ready() {
+            return Poll::Ready(());
+        }
+        self.writer.register(cx.waker());
+        if ready() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Copy bytes out of the ring. Returns `None` if it is empty. Must only be called by the
+    /// reader.
+    pub fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let head = self.head.load(SeqCst);
+        let tail = self.tail.load(SeqCst);
+        let n = buf.len().min(tail.wrapping_sub(head));
+        if n == 0 {
+            return None;
+        }
+
+        let start = head & self.mask;
+        let first = n.min(self.capacity() - start);
+        unsafe {
+            ptr::copy_nonoverlapping(self.buf.add(start), buf.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(self.buf, buf.as_mut_ptr().add(first), n - first);
+        }
+        self.head.store(head.wrapping_add(n), SeqCst);
+
+        // Only wake the writer if the ring was full, judging by the latest tail
+        if self.tail.load(SeqCst).wrapping_sub(head) == self.capacity() {
+            self.writer.wake();
+        }
+        Some(n)
+    }
+
+    /// Copy bytes into the ring. Returns `None` if it is full. Must only be called by the writer.
+    pub fn write(&self, buf: &[u8]) -> Option<usize> {
+        let head = self.head.load(SeqCst);
+        let tail = self.tail.load(SeqCst);
+        let n = buf.len().min(self.capacity() - tail.wrapping_sub(head));
+        if n == 0 {
+            return None;
+        }
+
+        let start = tail & self.mask;
+        let first = n.min(self.capacity() - start);
+        unsafe {
+            ptr::copy_nonoverlapping(buf.as_ptr(), self.buf.add(start), first);
+            ptr::copy_nonoverlapping(buf.as_ptr().add(first), self.buf, n - first);
+        }
+        self.tail.store(tail.wrapping_add(n), SeqCst);
+
+        // Only wake the reader if the ring was empty, judging by the latest head
+        if self.head.load(SeqCst) == tail {
+            self.reader.wake();
+        }
+        Some(n)
+    }
+
+    /// The reader has gone away
+    pub fn close_read(&self) {
+        self.read_closed.store(true, SeqCst);
+        self.writer.wake();
+    }
+
+    /// The writer is done writing
+    pub fn close_write(&self) {
+        self.write_closed.store(true, SeqCst);
+        self.reader.wake();
+    }
+
+    /// The writer went away without closing
+    pub fn reset(&self) {
+        self.reset.store(true, SeqCst);
+        self.close_write();
+    }
+
+    pub fn is_read_closed(&self) -> bool {
+        self.read_closed.load(SeqCst)
+    }
+
+    pub fn is_write_closed(&self) -> bool {
+        self.write_closed.load(SeqCst)
+    }
+
+    pub fn is_reset(&self) -> bool {
+        self.reset.load(SeqCst)
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        let buf = std::ptr::slice_from_raw_parts_mut(self.buf, self.capacity());
+        unsafe { drop(Box::from_raw(buf)) }
+    }
+}
@@ -0,0 +1,39 @@
+//! Tokio's `AsyncRead` and `AsyncWrite`, so codecs can be used on loopback sockets directly
+//! instead of through a compatibility wrapper.
+
+use crate::{FaultyLoopback, Loopback, Transport};
+use futures::io::{AsyncRead, AsyncWrite};
+use std::io::Result;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+macro_rules! impl_tokio_io {
+    ($($ty:ty),*) => {$(
+        impl tokio::io::AsyncRead for $ty {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                cx: &mut Context,
+                buf: &mut [u8],
+            ) -> Poll<Result<usize>> {
+                AsyncRead::poll_read(self, cx, buf)
+            }
+        }
+
+        impl tokio::io::AsyncWrite for $ty {
+            fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize>> {
+                AsyncWrite::poll_write(self, cx, buf)
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+                AsyncWrite::poll_flush(self, cx)
+            }
+
+            fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+                AsyncWrite::poll_close(self, cx)
+            }
+        }
+    )*};
+}
+
+// Covers `Connection` too, through tokio's impls for `Box`
+impl_tokio_io!(Loopback, FaultyLoopback, dyn Transport);
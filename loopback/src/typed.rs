@@ -0,0 +1,125 @@
+//! Typed channels between native services, which hand values across without serializing them.
+//! When the peer is a wasm module, the same `Channel` API is served over a byte stream instead,
+//! as length-delimited bincode frames.
+
+use crate::Connection;
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::sink::Sink;
+use futures::stream::Stream;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// Values in flight in each direction of a typed pair
+const TYPED_CAPACITY: usize = 32;
+
+/// One end of a typed pair, sending `S` and receiving `R`
+pub struct TypedEnd<S, R> {
+    tx: Sender<S>,
+    rx: Receiver<R>,
+}
+
+/// Create a pair of connected typed ends
+pub fn pair<A, B>() -> (TypedEnd<A, B>, TypedEnd<B, A>) {
+    let (a_tx, b_rx) = channel(TYPED_CAPACITY);
+    let (b_tx, a_rx) = channel(TYPED_CAPACITY);
+    (
+        TypedEnd { tx: a_tx, rx: a_rx },
+        TypedEnd { tx: b_tx, rx: b_rx },
+    )
+}
+
+/// A connection sending `S` and receiving `R`, directly to a native peer or serialized for a
+/// wasm one
+pub struct Channel<S, R> {
+    inner: Inner<S, R>,
+}
+
+enum Inner<S, R> {
+    Typed(TypedEnd<S, R>),
+    Serialized(
+        Framed<Connection, LengthDelimitedCodec>,
+        PhantomData<fn(S) -> R>,
+    ),
+}
+
+impl<S, R> Channel<S, R> {
+    /// Talk to a native peer through a typed end
+    pub fn typed(end: TypedEnd<S, R>) -> Self {
+        Self {
+            inner: Inner::Typed(end),
+        }
+    }
+
+    /// Talk to a peer over a byte stream, as length-delimited bincode frames
+    pub fn serialized(conn: Connection) -> Self {
+        Self {
+            inner: Inner::Serialized(Framed::new(conn, LengthDelimitedCodec::new()), PhantomData),
+        }
+    }
+
+    /// Returns true if values are passed without serializing them
+    pub fn is_typed(&self) -> bool {
+        matches!(self.inner, Inner::Typed(_))
+    }
+}
+
+fn disconnected<T>(_: T) -> io::Error {
+    io::Error::from(io::ErrorKind::BrokenPipe)
+}
+
+fn invalid_data(e: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl<S: Serialize, R> Sink<S> for Channel<S, R> {
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match &mut self.inner {
+            Inner::Typed(end) => end.tx.poll_ready(cx).map_err(disconnected),
+            Inner::Serialized(framed, _) => Pin::new(framed).poll_ready(cx),
+        }
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: S) -> io::Result<()> {
+        match &mut self.inner {
+            Inner::Typed(end) => end.tx.start_send(item).map_err(disconnected),
+            Inner::Serialized(framed, _) => {
+                let bytes = bincode::serialize(&item).map_err(invalid_data)?;
+                Pin::new(framed).start_send(bytes.into())
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match &mut self.inner {
+            Inner::Typed(end) => Pin::new(&mut end.tx).poll_flush(cx).map_err(disconnected),
+            Inner::Serialized(framed, _) => Pin::new(framed).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match &mut self.inner {
+            Inner::Typed(end) => Pin::new(&mut end.tx).poll_close(cx).map_err(disconnected),
+            Inner::Serialized(framed, _) => Pin::new(framed).poll_close(cx),
+        }
+    }
+}
+
+impl<S, R: DeserializeOwned> Stream for Channel<S, R> {
+    type Item = io::Result<R>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        match &mut self.inner {
+            Inner::Typed(end) => Pin::new(&mut end.rx).poll_next(cx).map(|v| v.map(Ok)),
+            Inner::Serialized(framed, _) => Pin::new(framed).poll_next(cx).map(|frame| {
+                frame.map(|frame| bincode::deserialize(&frame?).map_err(invalid_data))
+            }),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use futures::executor::LocalPool;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::task::{waker, ArcWake, SpawnExt};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use loopback::Loopback;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+#[derive(Default)]
+struct Flag(AtomicBool);
+
+impl ArcWake for Flag {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// Yield to the executor once, so the reader falls behind the writer
+async fn yield_now() {
+    let mut yielded = false;
+    futures::future::poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[test]
+fn fast_writer_slow_reader() {
+    const TOTAL: usize = 1 << 20;
+    let (mut writer, mut reader) = Loopback::pair();
+    let mut pool = LocalPool::new();
+    let spawner = pool.spawner();
+
+    spawner
+        .spawn(async move {
+            writer.write_all(&pattern(TOTAL)).await.unwrap();
+            writer.flush().await.unwrap();
+        })
+        .unwrap();
+
+    let received = pool.run_until(async move {
+        let mut received = Vec::new();
+        let mut buf = [0u8; 100];
+        while received.len() < TOTAL {
+            let n = reader.read(&mut buf).await.unwrap();
+            assert_ne!(n, 0);
+            received.extend_from_slice(&buf[..n]);
+            yield_now().await;
+        }
+        received
+    });
+
+    assert_eq!(received, pattern(TOTAL));
+}
+
+#[test]
+fn full_channel_is_pending_until_read() {
+    let (mut writer, mut reader) = Loopback::pair();
+    let flag = Arc::new(Flag::default());
+    let waker = waker(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    let chunk = pattern(4096);
+
+    // Nobody is reading, so the writer must eventually stop accepting data
+    let mut written = 0;
+    loop {
+        match Pin::new(&mut writer).poll_write(&mut cx, &chunk) {
+            Poll::Ready(Ok(n)) => {
+                assert_ne!(n, 0, "zero-length write for non-empty input");
+                written += n;
+            }
+            Poll::Ready(Err(e)) => panic!("write failed: {}", e),
+            Poll::Pending => break,
+        }
+        assert!(written < 64 << 20, "writes were never blocked");
+    }
+    assert!(!flag.0.load(Ordering::SeqCst));
+
+    // Reading frees up room, and wakes the writer
+    let mut buf = vec![0u8; written];
+    let mut read = 0;
+    while !flag.0.load(Ordering::SeqCst) {
+        match Pin::new(&mut reader).poll_read(&mut cx, &mut buf[read..]) {
+            Poll::Ready(Ok(n)) => read += n,
+            other => panic!("unexpected read result: {:?}", other),
+        }
+    }
+    match Pin::new(&mut writer).poll_write(&mut cx, &chunk) {
+        Poll::Ready(Ok(n)) => assert_ne!(n, 0),
+        other => panic!("write did not resume: {:?}", other),
+    }
+}
+
+#[test]
+fn empty_write_is_ready() {
+    let (mut writer, _reader) = Loopback::pair();
+    let flag = Arc::new(Flag::default());
+    let waker = waker(flag);
+    let mut cx = Context::from_waker(&waker);
+    match Pin::new(&mut writer).poll_write(&mut cx, &[]) {
+        Poll::Ready(Ok(0)) => (),
+        other => panic!("unexpected write result: {:?}", other),
+    }
+}
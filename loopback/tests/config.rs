@@ -0,0 +1,76 @@
+use futures::executor::block_on;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::task::noop_waker;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use loopback::{Loopback, LoopbackConfig};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+fn try_read(socket: &mut Loopback, buf: &mut [u8]) -> Poll<usize> {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    Pin::new(socket)
+        .poll_read(&mut cx, buf)
+        .map(|result| result.unwrap())
+}
+
+#[test]
+fn writes_held_back_until_threshold() {
+    let (mut a, mut b) = Loopback::pair_with(LoopbackConfig {
+        capacity: 1024,
+        flush_threshold: 8,
+    });
+    let mut buf = [0u8; 16];
+    block_on(async {
+        a.write_all(b"abc").await.unwrap();
+        assert_eq!(try_read(&mut b, &mut buf), Poll::Pending);
+
+        a.flush().await.unwrap();
+        assert_eq!(try_read(&mut b, &mut buf), Poll::Ready(3));
+
+        a.write_all(b"0123456789").await.unwrap();
+        assert_eq!(try_read(&mut b, &mut buf), Poll::Ready(8));
+        a.flush().await.unwrap();
+        assert_eq!(try_read(&mut b, &mut buf), Poll::Ready(2));
+    });
+}
+
+#[test]
+fn stats() {
+    let (mut a, mut b) = Loopback::pair_with(LoopbackConfig {
+        capacity: 16,
+        flush_threshold: 0,
+    });
+    block_on(async {
+        a.write_all(b"hello").await.unwrap();
+        a.write_all(b" world").await.unwrap();
+        let mut buf = [0u8; 11];
+        b.read_exact(&mut buf).await.unwrap();
+    });
+
+    let a = a.stats();
+    assert_eq!(a.bytes_written, 11);
+    assert_eq!(a.messages_written, 2);
+    assert_eq!(a.high_water, 11);
+    assert_eq!(b.stats().bytes_read, 11);
+
+    // The ring only holds 16 bytes, so the second write can't complete until it is read from
+    let (mut a, mut b) = Loopback::pair_with(LoopbackConfig {
+        capacity: 16,
+        flush_threshold: 0,
+    });
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let data = [1u8; 16];
+    assert_eq!(
+        Pin::new(&mut a)
+            .poll_write(&mut cx, &data)
+            .map(|r| r.unwrap()),
+        Poll::Ready(16)
+    );
+    assert!(Pin::new(&mut a).poll_write(&mut cx, &data).is_pending());
+    let mut buf = [0u8; 16];
+    assert_eq!(try_read(&mut b, &mut buf), Poll::Ready(16));
+    assert_eq!(a.stats().blocked_on_full, 1);
+    assert_eq!(a.stats().high_water, 16);
+}
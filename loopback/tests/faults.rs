@@ -0,0 +1,122 @@
+use futures::executor::block_on;
+use futures::future::join;
+use futures::task::noop_waker_ref;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use loopback::{Faults, FaultyLoopback, Loopback, Transport};
+use std::io::ErrorKind;
+use std::task::Context;
+use std::time::{Duration, Instant};
+
+fn faulty_pair(faults: Faults) -> (FaultyLoopback, Loopback) {
+    let (a, b) = Loopback::pair();
+    (FaultyLoopback::new(a, faults, 0), b)
+}
+
+/// Sizes of the writes `faults` splits a large write into
+fn chunk_sizes(faults: Faults) -> Vec<usize> {
+    let (mut a, mut b) = faulty_pair(faults);
+    let data = [7u8; 1000];
+    block_on(async {
+        let mut sizes = Vec::new();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let n = a.write(rest).await.unwrap();
+            sizes.push(n);
+            rest = &rest[n..];
+        }
+        let mut received = [0u8; 1000];
+        b.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received[..], &data[..]);
+        sizes
+    })
+}
+
+#[test]
+fn chunks_are_short_and_reproducible() {
+    let faults = Faults {
+        seed: 42,
+        max_chunk: Some(10),
+        ..Faults::default()
+    };
+    let sizes = chunk_sizes(faults.clone());
+    assert!(sizes.iter().all(|n| (1..=10).contains(n)));
+    assert_eq!(sizes, chunk_sizes(faults.clone()));
+    assert_ne!(sizes, chunk_sizes(Faults { seed: 43, ..faults }));
+}
+
+#[test]
+fn fails_after_limit() {
+    let (mut a, mut b) = faulty_pair(Faults {
+        fail_after: Some(100),
+        ..Faults::default()
+    });
+    block_on(async {
+        a.write_all(&[1u8; 99]).await.unwrap();
+        let err = a.write_all(&[1u8; 10]).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        let err = a.read(&mut [0u8; 10]).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+
+        // The peer sees a broken connection, not a clean end of file
+        let mut received = Vec::new();
+        let err = b.read_to_end(&mut received).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+    });
+}
+
+#[test]
+fn latency_delays_reads() {
+    let latency = Duration::from_millis(50);
+    let (mut a, mut b) = faulty_pair(Faults {
+        latency,
+        ..Faults::default()
+    });
+    let start = Instant::now();
+    block_on(join(async { b.write_all(b"ping").await.unwrap() }, async {
+        let mut buf = [0u8; 4];
+        a.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }));
+    assert!(start.elapsed() >= latency);
+}
+
+#[test]
+fn latency_counts_from_arrival() {
+    let latency = Duration::from_millis(50);
+    let (mut a, mut b) = faulty_pair(Faults {
+        latency,
+        ..Faults::default()
+    });
+    let mut cx = Context::from_waker(noop_waker_ref());
+    block_on(b.write_all(b"one")).unwrap();
+    assert!(!a.is_readable(&mut cx));
+
+    // Reading late doesn't add more latency, and later data has its own
+    std::thread::sleep(latency);
+    block_on(b.write_all(b"two")).unwrap();
+    let start = Instant::now();
+    let mut buf = [0u8; 6];
+    let n = block_on(a.read(&mut buf)).unwrap();
+    assert_eq!(&buf[..n], b"one");
+    assert!(start.elapsed() < latency);
+
+    block_on(a.read_exact(&mut buf[..3])).unwrap();
+    assert_eq!(&buf[..3], b"two");
+    assert!(start.elapsed() >= latency);
+}
+
+#[test]
+fn bandwidth_throttles_writes() {
+    let (mut a, mut b) = faulty_pair(Faults {
+        bandwidth: Some(10_000),
+        max_chunk: Some(100),
+        ..Faults::default()
+    });
+    let start = Instant::now();
+    block_on(async {
+        a.write_all(&[0u8; 1000]).await.unwrap();
+        b.read_exact(&mut [0u8; 1000]).await.unwrap();
+    });
+    // All but the last chunk has to wait for the throttle
+    assert!(start.elapsed() >= Duration::from_millis(80));
+}
@@ -0,0 +1,95 @@
+use futures::executor::block_on;
+use futures::task::noop_waker_ref;
+use futures::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use loopback::{Loopback, LoopbackConfig};
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::task::Context;
+
+#[test]
+fn reply_after_close() {
+    let (mut client, mut server) = Loopback::pair();
+    block_on(async {
+        client.write_all(b"request").await.unwrap();
+        client.close().await.unwrap();
+
+        let mut request = Vec::new();
+        server.read_to_end(&mut request).await.unwrap();
+        assert_eq!(request, b"request");
+
+        server.write_all(b"reply").await.unwrap();
+        server.close().await.unwrap();
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, b"reply");
+    });
+}
+
+#[test]
+fn eof_is_repeated() {
+    let (mut a, mut b) = Loopback::pair();
+    block_on(async {
+        a.close().await.unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(b.read(&mut buf).await.unwrap(), 0);
+        assert_eq!(b.read(&mut buf).await.unwrap(), 0);
+    });
+}
+
+#[test]
+fn write_after_close() {
+    let (mut a, _b) = Loopback::pair();
+    block_on(async {
+        a.close().await.unwrap();
+        let err = a.write(b"late").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+    });
+}
+
+#[test]
+fn drop_resets_after_buffered_data() {
+    let (mut a, mut b) = Loopback::pair();
+    block_on(async {
+        a.write_all(b"last words").await.unwrap();
+        drop(a);
+
+        let mut buf = [0u8; 10];
+        b.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"last words");
+        let err = b.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+
+        let err = b.write(b"anyone?").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+    });
+}
+
+#[test]
+fn shutdown_with_a_full_ring() {
+    let config = LoopbackConfig {
+        capacity: 16,
+        flush_threshold: 8,
+    };
+    let (mut a, mut b) = Loopback::pair_with(config);
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let sent: Vec<u8> = (0..24).collect();
+    block_on(async {
+        // The ring takes 16 bytes, and the last 8 wait in the write buffer
+        a.write_all(&sent).await.unwrap();
+        a.shutdown();
+        let err = a.write(b"late").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+
+        let mut buf = [0u8; 16];
+        b.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf[..], &sent[..16]);
+        // The rest hasn't been sent, so this is not end of file yet
+        assert!(Pin::new(&mut b).poll_read(&mut cx, &mut buf).is_pending());
+
+        a.close().await.unwrap();
+        let mut rest = Vec::new();
+        b.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, &sent[16..]);
+    });
+}
@@ -0,0 +1,32 @@
+use futures::executor::block_on;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use loopback::Loopback;
+use std::thread;
+
+#[test]
+fn transfer_between_threads() {
+    const TOTAL: usize = 8 << 20;
+    let data: Vec<u8> = (0..TOTAL).map(|i| (i % 253) as u8).collect();
+    let (mut writer, mut reader) = Loopback::pair();
+
+    let expected = data.clone();
+    let reader = thread::spawn(move || {
+        block_on(async {
+            let mut received = Vec::new();
+            let mut buf = [0u8; 3000];
+            while received.len() < TOTAL {
+                let n = reader.read(&mut buf).await.unwrap();
+                received.extend_from_slice(&buf[..n]);
+            }
+            assert!(received == expected);
+        })
+    });
+
+    block_on(async {
+        for chunk in data.chunks(7777) {
+            writer.write_all(chunk).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+    });
+    reader.join().unwrap();
+}
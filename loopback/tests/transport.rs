@@ -0,0 +1,54 @@
+use futures::executor::block_on;
+use futures::io::{AsyncRead, AsyncWrite, Result};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use loopback::{Connection, Stats, Transport};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Swallows writes and reads end of file, needing nothing from any of the crate's features
+struct Null;
+
+impl AsyncRead for Null {
+    fn poll_read(self: Pin<&mut Self>, _cx: &mut Context, _buf: &mut [u8]) -> Poll<Result<usize>> {
+        Poll::Ready(Ok(0))
+    }
+}
+
+impl AsyncWrite for Null {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context, buf: &[u8]) -> Poll<Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Transport for Null {
+    fn is_readable(&mut self, _cx: &mut Context) -> bool {
+        true
+    }
+
+    fn is_writable(&mut self, _cx: &mut Context) -> bool {
+        true
+    }
+
+    fn shutdown(&mut self) {}
+
+    fn stats(&self) -> Stats {
+        Stats::default()
+    }
+}
+
+#[test]
+fn outside_transports_are_connections() {
+    let mut conn: Connection = Box::new(Null);
+    block_on(async {
+        conn.write_all(b"into the void").await.unwrap();
+        assert_eq!(conn.read(&mut [0u8; 4]).await.unwrap(), 0);
+    });
+}
@@ -0,0 +1,33 @@
+#![cfg(feature = "typed")]
+use futures::executor::block_on;
+use futures::{SinkExt, StreamExt};
+use loopback::typed::{self, Channel};
+use loopback::{Connection, Loopback};
+
+async fn echo(
+    mut client: Channel<String, (u32, String)>,
+    mut server: Channel<(u32, String), String>,
+) {
+    client.send("hello".to_string()).await.unwrap();
+    let request = server.next().await.unwrap().unwrap();
+    server.send((request.len() as u32, request)).await.unwrap();
+    let reply = client.next().await.unwrap().unwrap();
+    assert_eq!(reply, (5, "hello".to_string()));
+}
+
+#[test]
+fn typed_pair() {
+    let (a, b) = typed::pair();
+    let (client, server) = (Channel::typed(a), Channel::typed(b));
+    assert!(client.is_typed());
+    block_on(echo(client, server));
+}
+
+#[test]
+fn serialized_fallback() {
+    let (a, b) = Loopback::pair();
+    let (a, b): (Connection, Connection) = (Box::new(a), Box::new(b));
+    let (client, server) = (Channel::serialized(a), Channel::serialized(b));
+    assert!(!client.is_typed());
+    block_on(echo(client, server));
+}
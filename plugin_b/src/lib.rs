@@ -1,17 +1,10 @@
 use futures::SinkExt;
 use futures::StreamExt;
-use libplugin::{debug, spawn, yield_now, AsyncReadExt, AsyncWriteExt, Socket};
+use libplugin::{debug, yield_now, AsyncReadExt, AsyncWriteExt, Socket};
 
-#[no_mangle]
-pub extern "C" fn main() {
+#[libplugin::main]
+async fn main() {
     debug("Client init!");
-    spawn(connect());
-    std::panic::set_hook(Box::new(|info| {
-        debug(&info.to_string());
-    }));
-}
-
-async fn connect() {
     debug("Client connecting...");
     let socket = Socket::connect("renderer", 0).unwrap().await.unwrap();
     debug("Client connected!");
@@ -0,0 +1,81 @@
+//! Versioning of the interface between the host and modules. libplugin exports `abi_version` and
+//! `abi_features`, and the host checks them before calling anything else in the module.
+
+use std::error::Error;
+use std::fmt;
+
+/// Bumped whenever an import or export changes in a way the other side can't detect. The host
+/// still runs every older version, through shims for whatever has changed since. Modules from
+/// before 2 pass handles as 32 bits, and are only handed handles which fit.
+///
+/// * 0: modules from before versioning, with a single-argument `wake` and a `run_tasks` which
+///   returns nothing
+/// * 1: `wake(handle, interest)`, and the `abi_version` and `abi_features` exports
+/// * 2: 64 bit generational handles
+pub const VERSION: u32 = 2;
+
+/// Timers: `timer_create` and `timer_poll`
+pub const TIMERS: u64 = 1 << 0;
+/// Entropy: `random`
+pub const RANDOM: u64 = 1 << 1;
+/// Peer identity: `socket_module` and `socket_port`
+pub const PEER_ADDR: u64 = 1 << 2;
+/// Error descriptions: `last_error_message`
+pub const ERROR_MESSAGES: u64 = 1 << 3;
+
+/// Names of the optional groups of imports, for diagnostics
+pub const FEATURE_NAMES: &[(u64, &str)] = &[
+    (TIMERS, "timers"),
+    (RANDOM, "random"),
+    (PEER_ADDR, "peer addresses"),
+    (ERROR_MESSAGES, "error messages"),
+];
+
+/// Every feature this version of the interface knows about
+pub const FEATURES: u64 = TIMERS | RANDOM | PEER_ADDR | ERROR_MESSAGES;
+
+/// Why a module can't be run
+#[derive(Debug, Clone, PartialEq)]
+pub enum AbiError {
+    /// The module was built against a newer interface
+    TooNew(u32),
+    /// The module needs features this host doesn't have
+    UnknownFeatures(u64),
+}
+
+impl fmt::Display for AbiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AbiError::TooNew(version) => write!(
+                f,
+                "module uses ABI version {}, but this host only supports up to {}; upgrade the host",
+                version, VERSION
+            ),
+            AbiError::UnknownFeatures(features) => {
+                write!(f, "module needs unknown features {:#x}; upgrade the host", features)
+            }
+        }
+    }
+}
+
+impl Error for AbiError {}
+
+/// Check that a module with this version and these features can be run
+pub fn check(version: u32, features: u64) -> Result<(), AbiError> {
+    if version > VERSION {
+        Err(AbiError::TooNew(version))
+    } else if features & !FEATURES != 0 {
+        Err(AbiError::UnknownFeatures(features & !FEATURES))
+    } else {
+        Ok(())
+    }
+}
+
+/// Names of the known features in `features`
+pub fn feature_names(features: u64) -> Vec<&'static str> {
+    FEATURE_NAMES
+        .iter()
+        .filter(|(bit, _)| features & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
@@ -0,0 +1,194 @@
+//! The imports the host provides to modules, declared once in `host_imports!` and generated for
+//! each side from there, so the two can't drift apart.
+//!
+//! Parameters are scalars, which are passed as they are, or byte buffers, which modules pass as a
+//! pointer and a length:
+//!
+//! * `bytes`: read by the host, given to `Imports` as `&[u8]`
+//! * `bytes_mut`: filled in by the host, given to `Imports` as `&[Cell<u8>]`
+//!
+//! Imports returning `Maybe` are implemented as returning `Poll<io::Result<u32>>`, and those
+//! returning `Handle` as `Poll<io::Result<Handle>>`. Both are encoded as a `Maybe` on the way back
+//! to the module.
+
+use std::cell::Cell;
+use std::io;
+use std::task::Poll;
+
+/// Calls `$callback!` with the declaration of every import, after any extra arguments in
+/// parentheses:
+///
+/// ```ignore
+/// protocols::host_imports!(my_generator, extra tokens);
+/// // expands to
+/// my_generator! {
+///     (extra tokens)
+///     fn connect(peer: bytes, port: u16) -> Handle;
+///     // ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! host_imports {
+    ($callback:ident $(, $($arg:tt)*)?) => {
+        $callback! {
+            ($($($arg)*)?)
+
+            /// Start connecting to a port on the module `peer`. Returns a handle to `listen` on for
+            /// the connection.
+            fn connect(peer: bytes, port: u16) -> Handle;
+            /// Listen on a port. Returns a handle to `listen` on for connections.
+            fn listener_create(port: u16) -> Handle;
+            /// Accept the next connection on a listener or connector. Returns a socket handle.
+            fn listen(handle: $crate::Handle) -> Handle;
+            /// Close any kind of handle
+            fn close(handle: $crate::Handle);
+
+            /// Read into `buffer`. Returns the number of bytes read, or 0 at end of file.
+            fn read(handle: $crate::Handle, buffer: bytes_mut) -> Maybe;
+            /// Write from `buffer`. Returns the number of bytes written.
+            fn write(handle: $crate::Handle, buffer: bytes) -> Maybe;
+            fn flush(handle: $crate::Handle) -> Maybe;
+            /// Shut down the write side of a socket
+            fn shutdown(handle: $crate::Handle) -> Maybe;
+
+            /// Create a timer which fires after `ms` milliseconds
+            fn timer_create(ms: u32) -> Handle;
+            /// Returns 0 once the timer has fired
+            fn timer_poll(handle: $crate::Handle) -> Maybe;
+
+            /// Fill `buffer` with random bytes
+            fn random(buffer: bytes_mut) -> Maybe;
+
+            /// Copy the id of the module at the local (0) or peer (1) end of a socket into
+            /// `buffer`. Returns the full length of the id.
+            fn socket_module(handle: $crate::Handle, peer: u32, buffer: bytes_mut) -> Maybe;
+            /// The port at the local (0) or peer (1) end of a socket
+            fn socket_port(handle: $crate::Handle, peer: u32) -> Maybe;
+            /// Copy the description of the most recent failure on a handle into `buffer`. Returns
+            /// the full length of the description.
+            fn last_error_message(handle: $crate::Handle, buffer: bytes_mut) -> Maybe;
+
+            /// Log a message from the module
+            fn debug(text: bytes);
+        }
+    };
+}
+
+macro_rules! imports_trait {
+    (() $($(#[$attr:meta])* fn $name:ident ($($params:tt)*) $(-> $ret:ident)?;)*) => {
+        /// The host's side of the imports
+        pub trait Imports {
+            $( __imports_trait_method!($(#[$attr])* $name [] ($($params)*) $(-> $ret)?); )*
+        }
+    };
+}
+
+macro_rules! __imports_trait_method {
+    ($(#[$attr:meta])* $name:ident [$($acc:tt)*] () -> Maybe) => {
+        $(#[$attr])*
+        fn $name(&mut self, $($acc)*) -> Poll<io::Result<u32>>;
+    };
+    ($(#[$attr:meta])* $name:ident [$($acc:tt)*] () -> Handle) => {
+        $(#[$attr])*
+        fn $name(&mut self, $($acc)*) -> Poll<io::Result<crate::Handle>>;
+    };
+    ($(#[$attr:meta])* $name:ident [$($acc:tt)*] ()) => {
+        $(#[$attr])*
+        fn $name(&mut self, $($acc)*);
+    };
+    ($(#[$attr:meta])* $name:ident [$($acc:tt)*] ($p:ident: bytes $(, $($rest:tt)*)?) $(-> $ret:ident)?) => {
+        __imports_trait_method!($(#[$attr])* $name [$($acc)* $p: &[u8],] ($($($rest)*)?) $(-> $ret)?);
+    };
+    ($(#[$attr:meta])* $name:ident [$($acc:tt)*] ($p:ident: bytes_mut $(, $($rest:tt)*)?) $(-> $ret:ident)?) => {
+        __imports_trait_method!($(#[$attr])* $name [$($acc)* $p: &[Cell<u8>],] ($($($rest)*)?) $(-> $ret)?);
+    };
+    ($(#[$attr:meta])* $name:ident [$($acc:tt)*] ($p:ident: $t:ty $(, $($rest:tt)*)?) $(-> $ret:ident)?) => {
+        __imports_trait_method!($(#[$attr])* $name [$($acc)* $p: $t,] ($($($rest)*)?) $(-> $ret)?);
+    };
+}
+
+host_imports!(imports_trait);
+
+/// Declares the imports for a module: `extern "C"` functions on wasm, and natively, functions of
+/// the same signature which call the `Imports` made by `$host` in place of the kernel.
+///
+/// ```ignore
+/// protocols::host_imports!(guest_imports, crate::mock::Host);
+/// ```
+#[macro_export]
+macro_rules! guest_imports {
+    (($host:expr) $($(#[$attr:meta])* fn $name:ident ($($params:tt)*) $(-> $ret:ident)?;)*) => {
+        $( $crate::__guest_import!({$host} $(#[$attr])* $name [] [] ($($params)*) $(-> $ret)?); )*
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __guest_import {
+    ({$host:expr} $(#[$attr:meta])* $name:ident [$($params:tt)*] [$($args:tt)*] () -> $ret:ident) => {
+        #[cfg(target_arch = "wasm32")]
+        extern "C" {
+            $(#[$attr])*
+            pub fn $name($($params)*) -> $crate::Maybe;
+        }
+
+        $(#[$attr])*
+        #[cfg(not(target_arch = "wasm32"))]
+        #[allow(clippy::missing_safety_doc)]
+        pub unsafe fn $name($($params)*) -> $crate::Maybe {
+            let mut host = $host;
+            $crate::__encode!(($ret) $crate::idl::Imports::$name(&mut host, $($args)*))
+        }
+    };
+    ({$host:expr} $(#[$attr:meta])* $name:ident [$($params:tt)*] [$($args:tt)*] ()) => {
+        #[cfg(target_arch = "wasm32")]
+        extern "C" {
+            $(#[$attr])*
+            pub fn $name($($params)*);
+        }
+
+        $(#[$attr])*
+        #[cfg(not(target_arch = "wasm32"))]
+        #[allow(clippy::missing_safety_doc)]
+        pub unsafe fn $name($($params)*) {
+            let mut host = $host;
+            $crate::idl::Imports::$name(&mut host, $($args)*)
+        }
+    };
+    ({$host:expr} $(#[$attr:meta])* $name:ident [$($params:tt)*] [$($args:tt)*] ($p:ident: bytes $(, $($rest:tt)*)?) $(-> $ret:ident)?) => {
+        $crate::__guest_import!(
+            {$host} $(#[$attr])* $name
+            [$($params)* $p: *const u8, len: usize,]
+            [$($args)* std::slice::from_raw_parts($p, len),]
+            ($($($rest)*)?) $(-> $ret)?
+        );
+    };
+    ({$host:expr} $(#[$attr:meta])* $name:ident [$($params:tt)*] [$($args:tt)*] ($p:ident: bytes_mut $(, $($rest:tt)*)?) $(-> $ret:ident)?) => {
+        $crate::__guest_import!(
+            {$host} $(#[$attr])* $name
+            [$($params)* $p: *mut u8, len: usize,]
+            [$($args)* std::cell::Cell::from_mut(std::slice::from_raw_parts_mut($p, len)).as_slice_of_cells(),]
+            ($($($rest)*)?) $(-> $ret)?
+        );
+    };
+    ({$host:expr} $(#[$attr:meta])* $name:ident [$($params:tt)*] [$($args:tt)*] ($p:ident: $t:ty $(, $($rest:tt)*)?) $(-> $ret:ident)?) => {
+        $crate::__guest_import!(
+            {$host} $(#[$attr])* $name
+            [$($params)* $p: $t,]
+            [$($args)* $p,]
+            ($($($rest)*)?) $(-> $ret)?
+        );
+    };
+}
+
+/// Turn what an `Imports` method returned into what the import returns
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __encode {
+    ((Maybe) $result:expr) => {
+        $crate::Maybe::from($result)
+    };
+    ((Handle) $result:expr) => {
+        $crate::Maybe::from_handle($result)
+    };
+}
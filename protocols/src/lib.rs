@@ -1,9 +1,82 @@
+pub mod abi;
+pub mod idl;
+
 use std::io::{self, ErrorKind};
 use std::task::Poll;
 
 pub type ModuleId = String;
 pub type Port = u16;
-pub type Handle = u32;
+/// Names a socket, listener, connector or timer. The low 32 bits are a slot, which is reused
+/// once the handle is closed, and the bits above are the slot's generation, which changes with
+/// each reuse so stale handles can be told apart from the live one. Generations wrap within
+/// `GENERATION_BITS`, keeping every handle within the values `Maybe` can carry.
+pub type Handle = u64;
+
+/// Bits of a handle given to the generation
+pub const GENERATION_BITS: u32 = 31;
+
+/// A port on a module
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Addr {
+    pub module: ModuleId,
+    pub port: Port,
+}
+
+/// What a task is waiting for on a handle. Tasks waiting for different things on the same handle
+/// are woken separately.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interest {
+    /// Data to read, end of file, or a timer firing
+    Readable = 0,
+    /// Room to write
+    Writable = 1,
+    /// A connection on a listener, or a connect completing
+    Acceptable = 2,
+}
+
+impl Interest {
+    pub fn from_u32(interest: u32) -> Option<Self> {
+        match interest {
+            0 => Some(Interest::Readable),
+            1 => Some(Interest::Writable),
+            2 => Some(Interest::Acceptable),
+            _ => None,
+        }
+    }
+}
+
+/// Error codes passed across the module ABI. Codes are never reused or renumbered, so modules
+/// and hosts built at different times agree on them; new kinds are added at the end.
+pub const ERROR_CODES: &[(i64, ErrorKind)] = &[
+    (-1, ErrorKind::WouldBlock),
+    (-2, ErrorKind::AlreadyExists),
+    (-3, ErrorKind::NotFound),
+    (-4, ErrorKind::NotConnected),
+    (-5, ErrorKind::ConnectionReset),
+    (-6, ErrorKind::BrokenPipe),
+    (-7, ErrorKind::InvalidData),
+    (-8, ErrorKind::InvalidInput),
+    (-9, ErrorKind::PermissionDenied),
+    (-10, ErrorKind::TimedOut),
+    (-11, ErrorKind::ConnectionRefused),
+    (-12, ErrorKind::ConnectionAborted),
+    (-13, ErrorKind::AddrInUse),
+    (-14, ErrorKind::AddrNotAvailable),
+    (-15, ErrorKind::UnexpectedEof),
+    (-16, ErrorKind::WriteZero),
+    (-17, ErrorKind::Interrupted),
+    (-18, ErrorKind::OutOfMemory),
+    (-19, ErrorKind::Unsupported),
+    (-20, ErrorKind::StaleNetworkFileHandle),
+];
+
+/// Code for errors without an entry in `ERROR_CODES`
+pub const OTHER_ERROR: i64 = i64::MIN;
+
+/// Failures which happen before there is a handle to blame, such as a bad peer name passed to
+/// `connect`, are recorded against this handle for `last_error_message`. It is never handed out.
+pub const NO_HANDLE: Handle = Handle::MAX;
 
 /// Either represents an error, or a u32
 #[repr(transparent)]
@@ -14,14 +87,43 @@ impl Maybe {
     pub fn errorkind(&self) -> Result<u32, io::ErrorKind> {
         match self.0 {
             e if e >= 0 => Ok(e as u32),
-            -1 => Err(ErrorKind::WouldBlock),
-            -2 => Err(ErrorKind::AlreadyExists),
-            -3 => Err(ErrorKind::NotFound),
-            -4 => Err(ErrorKind::NotConnected),
-            _ => Err(ErrorKind::Other),
+            e => Err(Self::kind(e)),
         }
     }
 
+    /// Decode the result of an import which returns a handle
+    pub fn handle(&self) -> Result<Handle, io::ErrorKind> {
+        match self.0 {
+            e if e >= 0 => Ok(e as Handle),
+            e => Err(Self::kind(e)),
+        }
+    }
+
+    /// Encode the result of an import which returns a handle
+    pub fn from_handle(poll: Poll<io::Result<Handle>>) -> Self {
+        Maybe(match poll {
+            Poll::Ready(Ok(handle)) => handle as i64,
+            Poll::Pending => Self::error_code(ErrorKind::WouldBlock),
+            Poll::Ready(Err(e)) => Self::error_code(e.kind()),
+        })
+    }
+
+    /// The kind of error for this code
+    fn kind(code: i64) -> ErrorKind {
+        ERROR_CODES
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map_or(ErrorKind::Other, |(_, kind)| *kind)
+    }
+
+    /// The code for errors of this kind
+    pub fn error_code(kind: ErrorKind) -> i64 {
+        ERROR_CODES
+            .iter()
+            .find(|(_, k)| *k == kind)
+            .map_or(OTHER_ERROR, |(code, _)| *code)
+    }
+
     pub fn into_poll(self) -> Poll<io::Result<u32>> {
         match self.errorkind() {
             Ok(n) => Poll::Ready(Ok(n)),
@@ -37,15 +139,6 @@ impl Maybe {
 
 impl From<Poll<io::Result<u32>>> for Maybe {
     fn from(poll: Poll<io::Result<u32>>) -> Self {
-        Maybe(match poll {
-            Poll::Ready(Ok(n)) => n as i64,
-            Poll::Pending => -1,
-            Poll::Ready(Err(e)) => match e.kind() {
-                ErrorKind::AlreadyExists => -2,
-                ErrorKind::NotFound => -3,
-                ErrorKind::NotConnected => -4,
-                _ => std::i64::MIN,
-            },
-        })
+        Self::from_handle(poll.map(|v| v.map(Handle::from)))
     }
 }
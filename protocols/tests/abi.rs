@@ -0,0 +1,36 @@
+use protocols::abi::{self, AbiError};
+
+#[test]
+fn current_modules_are_accepted() {
+    assert_eq!(abi::check(abi::VERSION, abi::FEATURES), Ok(()));
+}
+
+#[test]
+fn unversioned_modules_are_accepted() {
+    assert_eq!(abi::check(0, 0), Ok(()));
+}
+
+#[test]
+fn newer_modules_are_rejected() {
+    assert_eq!(
+        abi::check(abi::VERSION + 1, 0),
+        Err(AbiError::TooNew(abi::VERSION + 1))
+    );
+    let unknown = 1 << 63;
+    assert_eq!(
+        abi::check(abi::VERSION, abi::FEATURES | unknown),
+        Err(AbiError::UnknownFeatures(unknown))
+    );
+}
+
+#[test]
+fn feature_names() {
+    assert_eq!(
+        abi::feature_names(abi::TIMERS | abi::RANDOM),
+        ["timers", "random"]
+    );
+    assert_eq!(
+        abi::feature_names(abi::FEATURES).len(),
+        abi::FEATURE_NAMES.len()
+    );
+}
@@ -0,0 +1,153 @@
+use protocols::idl::Imports;
+use protocols::{guest_imports, Handle, Maybe};
+use std::cell::Cell;
+use std::io::{self, ErrorKind};
+use std::task::Poll;
+
+macro_rules! import_names {
+    (() $($(#[$attr:meta])* fn $name:ident ($($params:tt)*) $(-> $ret:ident)?;)*) => {
+        &[$(stringify!($name)),*]
+    };
+}
+
+#[test]
+fn imports_are_stable() {
+    // Modules built against older versions link against these
+    let names: &[&str] = protocols::host_imports!(import_names);
+    for name in &[
+        "connect",
+        "listener_create",
+        "listen",
+        "close",
+        "read",
+        "write",
+        "flush",
+        "debug",
+    ] {
+        assert!(names.contains(name), "{} is missing", name);
+    }
+}
+
+/// Reads back what was written, one byte at a time
+struct Echo;
+
+thread_local! {
+    static WRITTEN: Cell<Option<u8>> = const { Cell::new(None) };
+}
+
+impl Imports for Echo {
+    fn connect(&mut self, peer: &[u8], _port: u16) -> Poll<io::Result<Handle>> {
+        match peer {
+            b"echo" => Poll::Ready(Ok(1)),
+            _ => Poll::Ready(Err(ErrorKind::NotFound.into())),
+        }
+    }
+
+    fn listener_create(&mut self, _port: u16) -> Poll<io::Result<Handle>> {
+        Poll::Ready(Err(ErrorKind::Unsupported.into()))
+    }
+
+    fn listen(&mut self, _handle: Handle) -> Poll<io::Result<Handle>> {
+        Poll::Pending
+    }
+
+    fn close(&mut self, _handle: Handle) {}
+
+    fn read(&mut self, _handle: Handle, buffer: &[Cell<u8>]) -> Poll<io::Result<u32>> {
+        match WRITTEN.with(Cell::take) {
+            Some(byte) => {
+                buffer[0].set(byte);
+                Poll::Ready(Ok(1))
+            }
+            None => Poll::Pending,
+        }
+    }
+
+    fn write(&mut self, _handle: Handle, buffer: &[u8]) -> Poll<io::Result<u32>> {
+        WRITTEN.with(|written| written.set(Some(buffer[0])));
+        Poll::Ready(Ok(1))
+    }
+
+    fn flush(&mut self, _handle: Handle) -> Poll<io::Result<u32>> {
+        Poll::Ready(Ok(0))
+    }
+
+    fn shutdown(&mut self, _handle: Handle) -> Poll<io::Result<u32>> {
+        Poll::Ready(Ok(0))
+    }
+
+    fn timer_create(&mut self, _ms: u32) -> Poll<io::Result<Handle>> {
+        Poll::Ready(Err(ErrorKind::Unsupported.into()))
+    }
+
+    fn timer_poll(&mut self, _handle: Handle) -> Poll<io::Result<u32>> {
+        Poll::Ready(Err(ErrorKind::Unsupported.into()))
+    }
+
+    fn random(&mut self, buffer: &[Cell<u8>]) -> Poll<io::Result<u32>> {
+        buffer.iter().for_each(|b| b.set(4));
+        Poll::Ready(Ok(0))
+    }
+
+    fn socket_module(
+        &mut self,
+        _handle: Handle,
+        _peer: u32,
+        _buffer: &[Cell<u8>],
+    ) -> Poll<io::Result<u32>> {
+        Poll::Ready(Ok(0))
+    }
+
+    fn socket_port(&mut self, _handle: Handle, peer: u32) -> Poll<io::Result<u32>> {
+        Poll::Ready(Ok(5000 + peer))
+    }
+
+    fn last_error_message(
+        &mut self,
+        _handle: Handle,
+        _buffer: &[Cell<u8>],
+    ) -> Poll<io::Result<u32>> {
+        Poll::Ready(Ok(0))
+    }
+
+    fn debug(&mut self, _text: &[u8]) {}
+}
+
+mod imports {
+    use super::*;
+
+    protocols::host_imports!(guest_imports, Echo);
+}
+
+#[test]
+fn guest_imports_encode_results() {
+    unsafe {
+        let peer = b"echo";
+        assert_eq!(
+            imports::connect(peer.as_ptr(), peer.len(), 80).handle(),
+            Ok(1)
+        );
+        let peer = b"nobody";
+        let ret = imports::connect(peer.as_ptr(), peer.len(), 80);
+        assert_eq!(ret.errorkind(), Err(ErrorKind::NotFound));
+        assert_eq!(imports::listen(1).errorkind(), Err(ErrorKind::WouldBlock));
+        assert_eq!(imports::socket_port(1, 1).errorkind(), Ok(5001));
+        imports::close(1);
+    }
+}
+
+#[test]
+fn guest_imports_pass_buffers() {
+    let mut buffer = [0u8; 3];
+    unsafe {
+        assert!(imports::read(1, buffer.as_mut_ptr(), 1)
+            .into_poll()
+            .is_pending());
+        assert_eq!(imports::write(1, b"x".as_ptr(), 1).errorkind(), Ok(1));
+        assert_eq!(imports::read(1, buffer.as_mut_ptr(), 1).errorkind(), Ok(1));
+        assert_eq!(buffer[0], b'x');
+        let ret: Maybe = imports::random(buffer.as_mut_ptr(), buffer.len());
+        assert_eq!(ret.errorkind(), Ok(0));
+    }
+    assert_eq!(buffer, [4; 3]);
+}
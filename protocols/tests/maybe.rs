@@ -0,0 +1,50 @@
+use protocols::{Handle, Maybe, ERROR_CODES, GENERATION_BITS, OTHER_ERROR};
+use std::io::{self, ErrorKind};
+use std::task::Poll;
+
+#[test]
+fn error_codes_round_trip() {
+    for (code, kind) in ERROR_CODES {
+        assert!(*code < 0);
+        assert_eq!(Maybe::error_code(*kind), *code);
+        assert_eq!(Maybe(*code).errorkind(), Err(*kind));
+    }
+}
+
+#[test]
+fn error_codes_are_stable() {
+    // Modules built against older versions depend on these
+    assert_eq!(Maybe::error_code(ErrorKind::WouldBlock), -1);
+    assert_eq!(Maybe::error_code(ErrorKind::NotFound), -3);
+    assert_eq!(Maybe::error_code(ErrorKind::BrokenPipe), -6);
+    assert_eq!(Maybe::error_code(ErrorKind::InvalidData), -7);
+    assert_eq!(Maybe::error_code(ErrorKind::TimedOut), -10);
+}
+
+#[test]
+fn unknown_errors() {
+    let other = io::Error::from(ErrorKind::Other);
+    assert_eq!(Maybe::encode(Poll::Ready(Err(other))), OTHER_ERROR);
+    assert_eq!(Maybe(OTHER_ERROR).errorkind(), Err(ErrorKind::Other));
+    assert_eq!(Maybe(-1000).errorkind(), Err(ErrorKind::Other));
+}
+
+#[test]
+fn values_and_pending() {
+    assert_eq!(Maybe::encode(Poll::Ready(Ok(7))), 7);
+    assert!(Maybe(7).into_poll().is_ready());
+    assert!(Maybe::from(Poll::Pending).into_poll().is_pending());
+}
+
+#[test]
+fn handles_round_trip() {
+    let newest: Handle = (1 << (32 + GENERATION_BITS)) - 1;
+    for handle in &[0, 7, 1 << 32 | 7, newest] {
+        let maybe = Maybe::from_handle(Poll::Ready(Ok(*handle)));
+        assert!(maybe.0 >= 0);
+        assert_eq!(maybe.handle(), Ok(*handle));
+    }
+    let stale = io::Error::from(ErrorKind::StaleNetworkFileHandle);
+    let maybe = Maybe::from_handle(Poll::Ready(Err(stale)));
+    assert_eq!(maybe.handle(), Err(ErrorKind::StaleNetworkFileHandle));
+}
@@ -0,0 +1,123 @@
+//! Saving frames as images, for screenshots and for comparing against known good output
+
+use crate::software::{to_pixels, to_rgb, Canvas};
+use crate::*;
+use log::{error, info};
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where and how often frames are captured
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    /// Directory captures are saved in, created when first needed
+    pub dir: PathBuf,
+    /// Also capture every this many frames
+    pub every: Option<u64>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            dir: "captures".into(),
+            every: None,
+            width: 800,
+            height: 600,
+        }
+    }
+}
+
+/// One frame, as vectors and as pixels
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub svg: String,
+    pub png: Vec<u8>,
+}
+
+impl Capture {
+    /// Save as `name.svg` and `name.png` in `dir`
+    pub fn save(&self, dir: &Path, name: &str) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(format!("{}.svg", name)), &self.svg)?;
+        fs::write(dir.join(format!("{}.png", name)), &self.png)
+    }
+}
+
+impl Renderer {
+    pub fn set_capture(&mut self, config: CaptureConfig) {
+        self.capture_config = config;
+    }
+
+    /// Capture the next frame once it has been drawn
+    pub fn request_capture(&mut self) {
+        self.capture_requested = true;
+    }
+
+    /// The scene as it stands
+    pub fn capture(&self) -> io::Result<Capture> {
+        let (width, height) = (self.capture_config.width, self.capture_config.height);
+        let mut canvas = Canvas::new(width, height);
+        self.draw(&mut canvas);
+        Ok(Capture {
+            svg: self.svg(width, height),
+            png: encode_png(&canvas)?,
+        })
+    }
+
+    /// The scene as an SVG image, laid out as it is in the window
+    pub fn svg(&self, width: u32, height: u32) -> String {
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n\
+             <rect width=\"{w}\" height=\"{h}\" fill=\"black\"/>\n",
+            w = width,
+            h = height,
+        );
+        for (a, b, color) in self.lines() {
+            let (x1, y1) = to_pixels(&a, width, height);
+            let (x2, y2) = to_pixels(&b, width, height);
+            let [r, g, b] = to_rgb(&color);
+            let _ = writeln!(
+                svg,
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"rgb({},{},{})\"/>",
+                x1, y1, x2, y2, r, g, b
+            );
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Save frame number `frame` if it was asked for
+    pub(crate) fn capture_frame(&mut self, frame: u64) {
+        let every = match self.capture_config.every {
+            Some(every) => every > 0 && frame.is_multiple_of(every),
+            None => false,
+        };
+        if !std::mem::take(&mut self.capture_requested) && !every {
+            return;
+        }
+        let name = format!("frame-{:06}", frame);
+        let saved = self
+            .capture()
+            .and_then(|capture| capture.save(&self.capture_config.dir, &name));
+        match saved {
+            Ok(()) => info!("Captured {}", self.capture_config.dir.join(&name).display()),
+            Err(e) => error!("Failed to capture frame {}: {}", frame, e),
+        }
+    }
+}
+
+/// Encode a canvas as a PNG image
+pub fn encode_png(canvas: &Canvas) -> io::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    let mut encoder = ::png::Encoder::new(&mut png, canvas.width(), canvas.height());
+    encoder.set_color(::png::ColorType::RGB);
+    encoder.set_depth(::png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(canvas.pixels()))
+        .map_err(io::Error::other)?;
+    Ok(png)
+}
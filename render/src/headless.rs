@@ -0,0 +1,150 @@
+use crate::*;
+use futures::lock::Mutex;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Settings for drawing without a window
+#[derive(Debug, Clone)]
+pub struct HeadlessConfig {
+    /// Frames per second of the virtual clock
+    pub frame_rate: u32,
+    /// Keys to report as held, by virtual time
+    pub keys: KeyScript,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        Self {
+            frame_rate: 60,
+            keys: KeyScript::default(),
+        }
+    }
+}
+
+/// Keys held at each point in virtual time. Written one change per line, as the time in seconds
+/// followed by the keys held from then on: letters, or `Space`. Blank lines and lines starting
+/// with `#` are skipped.
+///
+/// ```text
+/// # Turn left while thrusting, then fire
+/// 0.5 W A
+/// 1.5 Space
+/// 2
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyScript {
+    /// Sorted by time
+    changes: Vec<(Duration, Vec<char>)>,
+}
+
+impl KeyScript {
+    /// Keys held at `time`
+    pub fn keys_at(&self, time: Duration) -> &[char] {
+        self.changes
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= time)
+            .map_or(&[], |(_, keys)| keys)
+    }
+}
+
+/// A line of a key script which couldn't be read
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyScriptError {
+    /// Counting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeyScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for KeyScriptError {}
+
+impl FromStr for KeyScript {
+    type Err = KeyScriptError;
+
+    fn from_str(script: &str) -> Result<Self, Self::Err> {
+        let mut changes = Vec::new();
+        for (i, line) in script.lines().enumerate() {
+            let error = |message: String| KeyScriptError {
+                line: i + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let time = words.next().unwrap_or_default();
+            let seconds: f64 = time
+                .parse()
+                .map_err(|_| error(format!("expected a time in seconds, found {:?}", time)))?;
+            if !seconds.is_finite() || seconds < 0.0 {
+                return Err(error(format!("time {} is out of range", seconds)));
+            }
+            let mut keys = Vec::new();
+            for word in words {
+                if word.eq_ignore_ascii_case("space") {
+                    keys.push(' ');
+                } else if word.chars().all(|c| c.is_ascii_alphabetic()) {
+                    keys.extend(word.chars().map(|c| c.to_ascii_uppercase()));
+                } else {
+                    return Err(error(format!("unknown key {:?}", word)));
+                }
+            }
+            changes.push((Duration::from_secs_f64(seconds), keys));
+        }
+        changes.sort_by_key(|(time, _)| *time);
+        Ok(Self { changes })
+    }
+}
+
+impl Renderer {
+    /// Run the scene without a window. Frames pass as soon as a client waits for one, each a
+    /// fixed step of virtual time after the last, so runs don't depend on how fast the machine
+    /// is. They are only drawn, in software, when captured.
+    pub fn headless(config: HeadlessConfig) -> Arc<Mutex<Self>> {
+        let instance = Self::shared();
+        let (wake, woken) = mpsc::channel();
+        Self::lock_blocking(&instance).wake_backend = Some(wake);
+        let ret = instance.clone();
+        std::thread::spawn(move || Self::headless_loop(instance, config, woken));
+        ret
+    }
+
+    fn headless_loop(share: Arc<Mutex<Self>>, config: HeadlessConfig, woken: Receiver<()>) {
+        loop {
+            let mut share = Self::lock_blocking(&share);
+
+            if let Some(done) = share.take_close_request() {
+                let _ = done.send(());
+                return;
+            }
+
+            if !share.is_awaited() {
+                drop(share);
+                // Woken by a client waiting for a frame, or by a request to close
+                if woken.recv().is_err() {
+                    return;
+                }
+                continue;
+            }
+
+            let time = virtual_time(share.frame(), config.frame_rate);
+            share.end_frame(config.keys.keys_at(time));
+        }
+    }
+}
+
+/// When frame number `frame` is drawn
+pub fn virtual_time(frame: u64, frame_rate: u32) -> Duration {
+    Duration::from_secs_f64(frame as f64 / f64::from(frame_rate.max(1)))
+}
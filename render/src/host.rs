@@ -1,29 +1,43 @@
+use crate::software::Canvas;
 use crate::*;
-use futures::lock::Mutex;
-use futures::{AsyncRead, AsyncWrite, SinkExt, StreamExt};
-use kiss3d::event::{Action, Key};
-use kiss3d::window::Window;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
-use tokio_util::compat::FuturesAsyncReadCompatExt;
+use futures::channel::oneshot;
+use futures::executor::block_on;
+use futures::lock::{Mutex, MutexGuard};
+use futures::StreamExt;
+use loopback::typed::Channel;
+use rpc::Reply;
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Arc};
 
+/// The scene shared between clients and whichever backend draws it
 pub struct Renderer {
     next_id: Id,
-    objects: HashMap<Id, ObjectData>,
-    waiting_for_frame: Vec<futures::channel::oneshot::Sender<FrameInfo>>,
+    /// Drawn in order of id, so overlapping lines always come out the same
+    objects: BTreeMap<Id, ObjectData>,
+    waiting_for_frame: Vec<Reply<FrameInfo, RendererResponse>>,
+    running: bool,
+    close_requested: Option<oneshot::Sender<()>>,
+    /// Wakes a backend which sleeps until there is something to do
+    pub(crate) wake_backend: Option<mpsc::Sender<()>>,
+    /// Frames drawn so far
+    frame: u64,
+    pub(crate) capture_config: CaptureConfig,
+    pub(crate) capture_requested: bool,
 }
 
 impl Renderer {
-    pub fn new(window_name: String) -> Arc<Mutex<Self>> {
-        let instance = Arc::new(Mutex::new(Self {
+    pub(crate) fn shared() -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
             next_id: 0,
-            objects: HashMap::new(),
+            objects: BTreeMap::new(),
             waiting_for_frame: Vec::new(),
-        }));
-        let ret = instance.clone();
-        std::thread::spawn(move || Self::render_loop(instance, window_name));
-        ret
+            running: true,
+            close_requested: None,
+            wake_backend: None,
+            frame: 0,
+            capture_config: CaptureConfig::default(),
+            capture_requested: false,
+        }))
     }
 
     pub fn next_id(&mut self) -> Id {
@@ -32,109 +46,123 @@ impl Renderer {
         ret
     }
 
+    /// Number of frames drawn so far
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Close the window, returning once the render thread has finished
+    pub async fn close(share: Arc<Mutex<Self>>) {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut share = share.lock().await;
+            if !share.running {
+                return;
+            }
+            share.close_requested = Some(tx);
+            share.wake();
+        }
+        let _ = rx.await;
+    }
+
     /// It is advisable to put this in its own task
     pub async fn handle_client(
         share: Arc<Mutex<Self>>,
-        socket: impl AsyncRead + AsyncWrite + Unpin,
+        channel: Channel<RendererMessage, RendererMessage>,
     ) {
-        let mut framed = Framed::new(socket.compat(), LengthDelimitedCodec::new());
-        while let Some(Ok(msg)) = framed.next().await {
-            let request: Request = bincode::deserialize(&msg).unwrap();
+        let mut calls = RendererCall::serve(channel);
+        while let Some(Ok(call)) = calls.next().await {
             let mut share = share.lock().await;
-            match request {
-                Request::WaitFrame => {
-                    // Set a wakeup routine on the renderer
-                    let (tx, rx) = futures::channel::oneshot::channel();
-                    share.waiting_for_frame.push(tx);
-
-                    // Release the lock so the renderer can actually render the frame
-                    drop(share);
-
-                    // Wake back up when there's data and pass it back to the client
-                    framed
-                        .send(bincode::serialize(&rx.await.unwrap()).unwrap().into())
-                        .await
-                        .unwrap();
+            match call {
+                // Answered by the render loop once the next frame is drawn
+                RendererCall::WaitFrame { reply } => {
+                    share.waiting_for_frame.push(reply);
+                    share.wake();
                 }
-                Request::DeleteObject(id) => {
+                RendererCall::DeleteObject { id } => {
                     share.objects.remove(&id);
                 }
-                Request::CreateObject(object) => {
+                RendererCall::AddObject { object, reply } => {
                     let id = share.next_id();
                     share.objects.insert(id, object);
-                    drop(share); // Release the lock early
-                    framed
-                        .send(bincode::serialize(&id).unwrap().into())
-                        .await
-                        .unwrap();
+                    reply.send(id);
                 }
-                Request::SetObjectTranslation(id, transform) => {
+                RendererCall::SetTransform { id, transform } => {
                     if let Some(object) = share.objects.get_mut(&id) {
                         object.transform = transform;
                     }
                 }
+                RendererCall::Capture {} => share.request_capture(),
             }
         }
     }
 
-    pub fn render_loop(share: Arc<Mutex<Self>>, window_name: String) {
-        let mut window = Window::new(&window_name);
-        while window.render() {
-            let mut share = loop {
-                if let Some(lock) = share.try_lock() {
-                    break lock;
-                }
-                std::thread::yield_now();
-            };
+    /// Lock from a render thread, which sleeps until the lock is free
+    pub(crate) fn lock_blocking(share: &Mutex<Self>) -> MutexGuard<'_, Self> {
+        block_on(share.lock())
+    }
 
-            for object in share.objects.values() {
-                for (a, b, color) in object.data.iter() {
-                    let a = object.transform.transform_point(a);
-                    let b = object.transform.transform_point(b);
-                    window.draw_planar_line(&a, &b, &color);
-                }
-            }
+    /// Let the backend know something has changed, if it is waiting to hear
+    fn wake(&self) {
+        if let Some(wake) = &self.wake_backend {
+            let _ = wake.send(());
+        }
+    }
 
-            // Wake up registered events
-            for waiter in share.waiting_for_frame.drain(..) {
-                let mut keys = Vec::new();
-                const KEY_SET: [(Key, char); 27] = [
-                    (Key::A, 'A'),
-                    (Key::B, 'B'),
-                    (Key::C, 'C'),
-                    (Key::D, 'D'),
-                    (Key::E, 'E'),
-                    (Key::F, 'F'),
-                    (Key::G, 'G'),
-                    (Key::H, 'H'),
-                    (Key::I, 'I'),
-                    (Key::J, 'J'),
-                    (Key::K, 'K'),
-                    (Key::L, 'L'),
-                    (Key::M, 'M'),
-                    (Key::N, 'N'),
-                    (Key::O, 'O'),
-                    (Key::P, 'P'),
-                    (Key::Q, 'Q'),
-                    (Key::R, 'R'),
-                    (Key::S, 'S'),
-                    (Key::T, 'T'),
-                    (Key::U, 'U'),
-                    (Key::V, 'V'),
-                    (Key::W, 'W'),
-                    (Key::X, 'X'),
-                    (Key::Y, 'Y'),
-                    (Key::Z, 'Z'),
-                    (Key::Space, ' '),
-                ];
-                for (key, response) in KEY_SET.iter() {
-                    match window.get_key(*key) {
-                        Action::Press => keys.push(*response),
-                        _ => (),
-                    }
-                }
-                waiter.send(FrameInfo { keys }).unwrap();
-            }
+    /// If `close` has been called, mark the renderer stopped and return who to tell once the
+    /// backend has shut down
+    pub(crate) fn take_close_request(&mut self) -> Option<oneshot::Sender<()>> {
+        let done = self.close_requested.take();
+        if done.is_some() {
+            self.running = false;
+        }
+        done
+    }
+
+    /// The backend stopped by itself, such as by the window being closed
+    #[cfg(feature = "window")]
+    pub(crate) fn stopped(share: &Mutex<Self>) {
+        let mut lock = Self::lock_blocking(share);
+        lock.running = false;
+        // Dropping any pending close request releases whoever asked for it
+        lock.close_requested = None;
+    }
+
+    /// Whether any client is waiting for the next frame
+    pub(crate) fn is_awaited(&self) -> bool {
+        !self.waiting_for_frame.is_empty()
+    }
+
+    /// Every line in the scene, in world coordinates
+    pub(crate) fn lines(&self) -> impl Iterator<Item = Line> + '_ {
+        self.objects.values().flat_map(|object| {
+            object.data.iter().map(move |(a, b, color)| {
+                (
+                    object.transform.transform_point(a),
+                    object.transform.transform_point(b),
+                    *color,
+                )
+            })
+        })
+    }
+
+    /// Draw the scene into `canvas`
+    pub fn draw(&self, canvas: &mut Canvas) {
+        canvas.clear();
+        for (a, b, color) in self.lines() {
+            canvas.draw_line(&a, &b, &color);
+        }
+    }
+
+    /// Count a frame as drawn, capture it if asked to, and answer everyone waiting for it with the
+    /// keys held during it
+    pub(crate) fn end_frame(&mut self, keys: &[char]) {
+        self.capture_frame(self.frame);
+        self.frame += 1;
+        for waiter in self.waiting_for_frame.drain(..) {
+            waiter.send(FrameInfo {
+                keys: keys.to_vec(),
+            });
         }
     }
 }
@@ -1,15 +1,22 @@
 #[cfg(feature = "host")]
+mod capture;
+#[cfg(feature = "host")]
+mod headless;
+#[cfg(feature = "host")]
 mod host;
+pub mod software;
+#[cfg(feature = "window")]
+mod window;
 
+#[cfg(feature = "host")]
+pub use capture::*;
+#[cfg(feature = "host")]
+pub use headless::*;
 #[cfg(feature = "host")]
 pub use host::*;
 
-use futures::{AsyncRead, AsyncWrite, SinkExt, StreamExt};
 pub use nalgebra::{Isometry2, Point2, Point3, Vector2, Vector3, Rotation2};
 use serde::{Deserialize, Serialize};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
-use tokio_util::compat::Compat;
-use tokio_util::compat::FuturesAsyncReadCompatExt;
 
 pub type Line = (Point2<f32>, Point2<f32>, Point3<f32>);
 
@@ -21,16 +28,18 @@ pub struct ObjectData {
 
 pub type Id = u64;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum Request {
-    CreateObject(ObjectData),
-    SetObjectTranslation(Id, Isometry2<f32>),
-    DeleteObject(Id),
-    WaitFrame,
-}
-
-pub struct RendererConn<S> {
-    socket: Framed<Compat<S>, LengthDelimitedCodec>,
+rpc::service! {
+    /// Client for the vector graphics renderer
+    pub service Renderer {
+        /// Add an object, returning its id
+        fn add_object(object: ObjectData) -> Id;
+        /// Wait for the next frame to be drawn
+        fn wait_frame() -> FrameInfo;
+        notify set_transform(id: Id, transform: Isometry2<f32>);
+        notify delete_object(id: Id);
+        /// Save the next frame to the host's capture directory
+        notify capture();
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -45,56 +54,3 @@ impl ObjectData {
         }
     }
 }
-
-impl<S: AsyncRead + AsyncWrite + Unpin> RendererConn<S> {
-    pub fn new(socket: S) -> Self {
-        Self {
-            socket: Framed::new(socket.compat(), LengthDelimitedCodec::new()),
-        }
-    }
-
-    // TODO: Make this fallible
-    pub async fn add_object(&mut self, object: ObjectData) -> Id {
-        self.socket
-            .send(
-                bincode::serialize(&Request::CreateObject(object))
-                    .unwrap()
-                    .into(),
-            )
-            .await
-            .unwrap();
-        bincode::deserialize(&self.socket.next().await.unwrap().unwrap()).unwrap()
-    }
-
-    // TODO: Make this fallible
-    pub async fn delete_object(&mut self, id: Id) {
-        self.socket
-            .send(
-                bincode::serialize(&Request::DeleteObject(id))
-                    .unwrap()
-                    .into(),
-            )
-            .await
-            .unwrap();
-    }
-
-    pub async fn set_transform(&mut self, id: Id, transform: Isometry2<f32>) {
-        self.socket
-            .send(
-                bincode::serialize(&Request::SetObjectTranslation(id, transform))
-                    .unwrap()
-                    .into(),
-            )
-            .await
-            .unwrap();
-    }
-
-    pub async fn wait_frame(&mut self) -> FrameInfo {
-        self.socket
-            .send(bincode::serialize(&Request::WaitFrame).unwrap().into())
-            .await
-            .unwrap();
-        let msg = self.socket.next().await.unwrap().unwrap();
-        bincode::deserialize(&msg).unwrap()
-    }
-}
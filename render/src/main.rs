@@ -1,33 +1,43 @@
 use futures::executor::LocalPool;
 use futures::task::SpawnExt;
-use loopback::Loopback;
-use nalgebra::{Point3, Translation3};
+use loopback::typed::{self, Channel};
 use render::*;
 
 fn main() {
     let mut pool = LocalPool::new();
     let spawner = pool.spawner();
-    let (a, b) = Loopback::pair();
+    // Both ends are native, so calls are passed across without being serialized
+    let (client, server) = typed::pair();
     let renderer = Renderer::new("UwU".into());
     spawner
-        .spawn(Renderer::handle_client(renderer.clone(), b))
+        .spawn(Renderer::handle_client(
+            renderer.clone(),
+            Channel::typed(server),
+        ))
         .unwrap();
+    let (conn, driver) = RendererClient::new(Channel::typed(client));
     spawner
         .spawn(async move {
-            let mut conn = RendererObjects::new(a);
-            let id = conn.add_object(ObjectData {
-                data: Box::new([(
-                    Point3::origin(),
-                    Point3::new(1.0, 1.0, 1.0),
+            driver.await.unwrap();
+        })
+        .unwrap();
+    spawner
+        .spawn(async move {
+            let object = ObjectData::new(
+                Box::new([(
+                    Point2::origin(),
+                    Point2::new(1.0, 1.0),
                     Point3::new(1.0, 1.0, 1.0),
                 )]),
-                transform: Translation3::identity(),
-            })
-            .await;
+                Isometry2::identity(),
+            );
+            let id = conn.add_object(object).await.unwrap();
             let mut i: f32 = 0.0;
             loop {
-                conn.set_transform(id, Translation3::new(i.cos(), 0.0, 0.0)).await;
-                i += 0.0001;
+                let transform = Isometry2::new(Vector2::new(i.cos(), 0.0), 0.0);
+                conn.set_transform(id, transform).unwrap();
+                conn.wait_frame().await.unwrap();
+                i += 0.01;
             }
         })
         .unwrap();
@@ -0,0 +1,117 @@
+//! Drawing without a GPU, for running the renderer on machines without a display
+
+use crate::{Point2, Point3};
+
+/// An RGB image the scene can be drawn into. World coordinates are pixels from the centre with y
+/// pointing up, as in the window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    /// Rows from the top, three bytes per pixel
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    /// A black canvas
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 3],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// RGB bytes, row by row from the top
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Colour of the pixel `x` across and `y` down from the top left
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 3] {
+        let i = self.index(x, y);
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.iter_mut().for_each(|p| *p = 0);
+    }
+
+    /// Draw a one pixel wide line. Colour channels range from 0 to 1.
+    pub fn draw_line(&mut self, a: &Point2<f32>, b: &Point2<f32>, color: &Point3<f32>) {
+        let (x0, y0) = to_pixels(a, self.width, self.height);
+        let (x1, y1) = to_pixels(b, self.width, self.height);
+        let (x0, y0, x1, y1) = match self.clip(x0, y0, x1, y1) {
+            Some(line) => line,
+            None => return,
+        };
+        let rgb = to_rgb(color);
+
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as u32;
+        for step in 0..=steps {
+            let t = step as f32 / steps as f32;
+            let x = (x0 + (x1 - x0) * t).floor();
+            let y = (y0 + (y1 - y0) * t).floor();
+            if x >= 0.0 && y >= 0.0 && x < self.width as f32 && y < self.height as f32 {
+                let i = self.index(x as u32, y as u32);
+                self.pixels[i..i + 3].copy_from_slice(&rgb);
+            }
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y as usize * self.width as usize + x as usize) * 3
+    }
+
+    /// Cut a line down to the part inside the canvas (Liang-Barsky), so lines far off screen
+    /// don't cost anything to draw
+    fn clip(&self, x0: f32, y0: f32, x1: f32, y1: f32) -> Option<(f32, f32, f32, f32)> {
+        let (dx, dy) = (x1 - x0, y1 - y0);
+        let (mut t0, mut t1) = (0.0f32, 1.0f32);
+        let edges = [
+            (-dx, x0),
+            (dx, self.width as f32 - x0),
+            (-dy, y0),
+            (dy, self.height as f32 - y0),
+        ];
+        for (p, q) in edges.iter() {
+            if *p == 0.0 {
+                if *q < 0.0 {
+                    return None;
+                }
+            } else {
+                let t = q / p;
+                if *p < 0.0 {
+                    t0 = t0.max(t);
+                } else {
+                    t1 = t1.min(t);
+                }
+            }
+        }
+        if t0 > t1 {
+            return None;
+        }
+        Some((x0 + dx * t0, y0 + dy * t0, x0 + dx * t1, y0 + dy * t1))
+    }
+}
+
+/// Where a point in world coordinates lands on an image, from the top left
+pub(crate) fn to_pixels(point: &Point2<f32>, width: u32, height: u32) -> (f32, f32) {
+    (point.x + width as f32 / 2.0, height as f32 / 2.0 - point.y)
+}
+
+pub(crate) fn to_rgb(color: &Point3<f32>) -> [u8; 3] {
+    [channel(color.x), channel(color.y), channel(color.z)]
+}
+
+fn channel(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
@@ -0,0 +1,79 @@
+use crate::*;
+use futures::lock::Mutex;
+use kiss3d::event::{Action, Key, WindowEvent};
+use kiss3d::window::Window;
+use std::sync::Arc;
+
+const KEY_SET: [(Key, char); 27] = [
+    (Key::A, 'A'),
+    (Key::B, 'B'),
+    (Key::C, 'C'),
+    (Key::D, 'D'),
+    (Key::E, 'E'),
+    (Key::F, 'F'),
+    (Key::G, 'G'),
+    (Key::H, 'H'),
+    (Key::I, 'I'),
+    (Key::J, 'J'),
+    (Key::K, 'K'),
+    (Key::L, 'L'),
+    (Key::M, 'M'),
+    (Key::N, 'N'),
+    (Key::O, 'O'),
+    (Key::P, 'P'),
+    (Key::Q, 'Q'),
+    (Key::R, 'R'),
+    (Key::S, 'S'),
+    (Key::T, 'T'),
+    (Key::U, 'U'),
+    (Key::V, 'V'),
+    (Key::W, 'W'),
+    (Key::X, 'X'),
+    (Key::Y, 'Y'),
+    (Key::Z, 'Z'),
+    (Key::Space, ' '),
+];
+
+impl Renderer {
+    /// Draw the scene in a window
+    pub fn new(window_name: String) -> Arc<Mutex<Self>> {
+        let instance = Self::shared();
+        let ret = instance.clone();
+        std::thread::spawn(move || Self::render_loop(instance, window_name));
+        ret
+    }
+
+    pub fn render_loop(share: Arc<Mutex<Self>>, window_name: String) {
+        let mut window = Window::new(&window_name);
+        while window.render() {
+            let mut share = Self::lock_blocking(&share);
+
+            if let Some(done) = share.take_close_request() {
+                window.close();
+                let _ = done.send(());
+                return;
+            }
+
+            for event in window.events().iter() {
+                if let WindowEvent::Key(Key::F12, Action::Press, _) = event.value {
+                    share.request_capture();
+                }
+            }
+
+            for (a, b, color) in share.lines() {
+                window.draw_planar_line(&a, &b, &color);
+            }
+
+            // Wake up registered events
+            let keys: Vec<char> = KEY_SET
+                .iter()
+                .filter(|(key, _)| matches!(window.get_key(*key), Action::Press))
+                .map(|(_, response)| *response)
+                .collect();
+            share.end_frame(&keys);
+        }
+
+        // The window was closed by the user
+        Self::stopped(&share);
+    }
+}
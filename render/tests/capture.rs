@@ -0,0 +1,119 @@
+use futures::executor::LocalPool;
+use futures::lock::Mutex;
+use futures::task::SpawnExt;
+use loopback::typed::{self, Channel};
+use render::*;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn capture_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("render-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn line() -> ObjectData {
+    ObjectData::new(
+        Box::new([(
+            Point2::origin(),
+            Point2::new(10.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+        )]),
+        Isometry2::translation(0.0, 5.0),
+    )
+}
+
+fn connect(pool: &LocalPool, renderer: &Arc<Mutex<Renderer>>) -> RendererClient {
+    let spawner = pool.spawner();
+    let (client, server) = typed::pair();
+    spawner
+        .spawn(Renderer::handle_client(
+            renderer.clone(),
+            Channel::typed(server),
+        ))
+        .unwrap();
+    let (conn, driver) = RendererClient::new(Channel::typed(client));
+    spawner
+        .spawn(async move {
+            driver.await.unwrap();
+        })
+        .unwrap();
+    conn
+}
+
+#[test]
+fn clients_can_capture_frames() {
+    let dir = capture_dir("client");
+    let renderer = Renderer::headless(HeadlessConfig::default());
+
+    let mut pool = LocalPool::new();
+    pool.run_until(renderer.lock()).set_capture(CaptureConfig {
+        dir: dir.clone(),
+        width: 40,
+        height: 30,
+        ..Default::default()
+    });
+    let conn = connect(&pool, &renderer);
+
+    pool.run_until(async move {
+        conn.add_object(line()).await.unwrap();
+        conn.wait_frame().await.unwrap();
+        conn.capture().unwrap();
+        conn.wait_frame().await.unwrap();
+        conn.wait_frame().await.unwrap();
+    });
+
+    // Only the frame after the request is saved, with the transform applied
+    assert!(!dir.join("frame-000000.svg").exists());
+    assert!(!dir.join("frame-000002.svg").exists());
+    let svg = fs::read_to_string(dir.join("frame-000001.svg")).unwrap();
+    assert!(svg.contains(r#"width="40" height="30""#));
+    assert!(svg.contains(r#"<line x1="20" y1="10" x2="30" y2="10" stroke="rgb(255,0,0)"/>"#));
+    let png = fs::read(dir.join("frame-000001.png")).unwrap();
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+    pool.run_until(Renderer::close(renderer));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn every_nth_frame_is_captured() {
+    let dir = capture_dir("every");
+    let renderer = Renderer::headless(HeadlessConfig::default());
+
+    let mut pool = LocalPool::new();
+    pool.run_until(renderer.lock()).set_capture(CaptureConfig {
+        dir: dir.clone(),
+        every: Some(3),
+        ..Default::default()
+    });
+    let conn = connect(&pool, &renderer);
+    pool.run_until(async move {
+        for _ in 0..7 {
+            conn.wait_frame().await.unwrap();
+        }
+    });
+    let frames = pool.run_until(async { renderer.lock().await.frame() });
+    assert_eq!(frames, 7);
+
+    let mut saved: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    saved.sort();
+    assert_eq!(
+        saved,
+        [
+            "frame-000000.png",
+            "frame-000000.svg",
+            "frame-000003.png",
+            "frame-000003.svg",
+            "frame-000006.png",
+            "frame-000006.svg",
+        ]
+    );
+
+    pool.run_until(Renderer::close(renderer));
+    fs::remove_dir_all(dir).unwrap();
+}
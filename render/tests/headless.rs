@@ -0,0 +1,91 @@
+use futures::executor::LocalPool;
+use futures::task::SpawnExt;
+use loopback::typed::{self, Channel};
+use render::software::Canvas;
+use render::*;
+use std::time::Duration;
+
+#[test]
+fn lines_are_drawn_from_the_centre() {
+    let mut canvas = Canvas::new(20, 10);
+    let white = Point3::new(1.0, 1.0, 1.0);
+    canvas.draw_line(&Point2::new(-5.0, 2.0), &Point2::new(5.0, 2.0), &white);
+    assert_eq!(canvas.pixel(5, 3), [255, 255, 255]);
+    assert_eq!(canvas.pixel(15, 3), [255, 255, 255]);
+    assert_eq!(canvas.pixel(10, 3), [255, 255, 255]);
+    assert_eq!(canvas.pixel(10, 4), [0, 0, 0]);
+    assert_eq!(canvas.pixel(4, 3), [0, 0, 0]);
+
+    // Lines leaving the canvas are clipped rather than wrapped
+    let red = Point3::new(1.0, 0.0, 0.0);
+    canvas.draw_line(&Point2::new(0.0, -1.0e6), &Point2::new(0.0, 1.0e6), &red);
+    assert_eq!(canvas.pixel(10, 0), [255, 0, 0]);
+    assert_eq!(canvas.pixel(10, 9), [255, 0, 0]);
+    assert_eq!(canvas.pixels().len(), 20 * 10 * 3);
+
+    canvas.clear();
+    assert!(canvas.pixels().iter().all(|p| *p == 0));
+}
+
+#[test]
+fn key_scripts() {
+    let script: KeyScript = "# Thrust, then turn and fire\n0.5 W\n\n1 a Space\n2\n"
+        .parse()
+        .unwrap();
+    assert!(script.keys_at(Duration::from_millis(0)).is_empty());
+    assert_eq!(script.keys_at(Duration::from_millis(500)), ['W']);
+    assert_eq!(script.keys_at(Duration::from_millis(1500)), ['A', ' ']);
+    assert!(script.keys_at(Duration::from_secs(3)).is_empty());
+
+    let error = "0 W\nsoon W".parse::<KeyScript>().unwrap_err();
+    assert_eq!(error.line, 2);
+    assert!("1 W!".parse::<KeyScript>().is_err());
+    assert!("-1 W".parse::<KeyScript>().is_err());
+}
+
+#[test]
+fn frames_follow_the_virtual_clock() {
+    let config = HeadlessConfig {
+        frame_rate: 10,
+        keys: "0.2 Space".parse().unwrap(),
+    };
+    let renderer = Renderer::headless(config);
+
+    let mut pool = LocalPool::new();
+    let spawner = pool.spawner();
+    let (client, server) = typed::pair();
+    spawner
+        .spawn(Renderer::handle_client(
+            renderer.clone(),
+            Channel::typed(server),
+        ))
+        .unwrap();
+    let (conn, driver) = RendererClient::new(Channel::typed(client));
+    spawner
+        .spawn(async move {
+            driver.await.unwrap();
+        })
+        .unwrap();
+
+    let keys = pool.run_until(async move {
+        let object = ObjectData::new(
+            Box::new([(
+                Point2::origin(),
+                Point2::new(10.0, 0.0),
+                Point3::new(1.0, 1.0, 1.0),
+            )]),
+            Isometry2::identity(),
+        );
+        conn.add_object(object).await.unwrap();
+        let mut keys = Vec::new();
+        for _ in 0..4 {
+            keys.push(conn.wait_frame().await.unwrap().keys);
+        }
+        keys
+    });
+    // Frames are a tenth of a second apart however long they take to draw
+    assert_eq!(keys, [vec![], vec![], vec![' '], vec![' ']]);
+    assert_eq!(pool.run_until(async { renderer.lock().await.frame() }), 4);
+
+    pool.run_until(Renderer::close(renderer));
+}
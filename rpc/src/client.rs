@@ -0,0 +1,148 @@
+use crate::{Error, Message, Transport};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::channel::oneshot;
+use futures::future::Future;
+use futures::stream::{FusedStream, StreamExt};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+type ReplySender<Resp> = oneshot::Sender<Result<Resp, Error>>;
+
+struct Outgoing<Req, Resp> {
+    request: Req,
+    reply: Option<ReplySender<Resp>>,
+}
+
+/// Makes calls to a service. Cheap to clone; every clone shares the same connection.
+pub struct Client<Req, Resp> {
+    queue: UnboundedSender<Outgoing<Req, Resp>>,
+}
+
+impl<Req, Resp> Clone for Client<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> Client<Req, Resp> {
+    /// Start talking to a service over `transport`. Nothing is sent or received unless the
+    /// returned driver is polled, so it is usually spawned as its own task.
+    pub fn new<T>(transport: T) -> (Self, Driver<T, Req, Resp>)
+    where
+        T: Transport<Message<Req, Resp>>,
+    {
+        let (queue, outgoing) = unbounded();
+        let driver = Driver {
+            transport,
+            outgoing,
+            pending: HashMap::new(),
+            next_id: 0,
+            _message: PhantomData,
+        };
+        (Self { queue }, driver)
+    }
+
+    /// Queue a call straight away, returning a future for its reply. Further calls may be made
+    /// before awaiting it.
+    pub fn call(&self, request: Req) -> impl Future<Output = Result<Resp, Error>> {
+        let (tx, rx) = oneshot::channel();
+        let queued = self.queue.unbounded_send(Outgoing {
+            request,
+            reply: Some(tx),
+        });
+        async move {
+            queued.map_err(|_| Error::Disconnected)?;
+            rx.await.map_err(|_| Error::Disconnected)?
+        }
+    }
+
+    /// Queue a call without a reply
+    pub fn notify(&self, request: Req) -> Result<(), Error> {
+        self.queue
+            .unbounded_send(Outgoing {
+                request,
+                reply: None,
+            })
+            .map_err(|_| Error::Disconnected)
+    }
+}
+
+/// Sends queued calls and hands out their replies. Finishes once every client has been dropped
+/// and every reply has arrived, or with an error if the transport fails. Calls still waiting
+/// when it finishes fail with `Disconnected`.
+pub struct Driver<T, Req, Resp> {
+    transport: T,
+    outgoing: UnboundedReceiver<Outgoing<Req, Resp>>,
+    pending: HashMap<u64, ReplySender<Resp>>,
+    next_id: u64,
+    _message: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<T, Req, Resp> Driver<T, Req, Resp>
+where
+    T: Transport<Message<Req, Resp>>,
+{
+    fn poll_send(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        while !self.outgoing.is_terminated() {
+            if Pin::new(&mut self.transport).poll_ready(cx)?.is_pending() {
+                break;
+            }
+            let Outgoing { request, reply } = match self.outgoing.poll_next_unpin(cx) {
+                Poll::Ready(Some(outgoing)) => outgoing,
+                _ => break,
+            };
+            let message = match reply {
+                Some(reply) => {
+                    let id = self.next_id;
+                    self.next_id += 1;
+                    self.pending.insert(id, reply);
+                    Message::Call(id, request)
+                }
+                None => Message::Notify(request),
+            };
+            Pin::new(&mut self.transport).start_send(message)?;
+        }
+        Pin::new(&mut self.transport)
+            .poll_flush(cx)
+            .map_err(Error::from)
+    }
+}
+
+impl<T, Req, Resp> Future for Driver<T, Req, Resp>
+where
+    T: Transport<Message<Req, Resp>>,
+{
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let flushed = this.poll_send(cx)?.is_ready();
+
+        loop {
+            match this.transport.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(Message::Reply(id, response)))) => {
+                    if let Some(reply) = this.pending.remove(&id) {
+                        // The caller may have lost interest
+                        let _ = reply.send(response.ok_or(Error::Unanswered));
+                    }
+                }
+                Poll::Ready(Some(Ok(_))) => return Poll::Ready(Err(Error::Unexpected)),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e.into())),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => break,
+            }
+        }
+
+        if flushed && this.outgoing.is_terminated() && this.pending.is_empty() {
+            Pin::new(&mut this.transport)
+                .poll_close(cx)
+                .map_err(Error::from)
+        } else {
+            Poll::Pending
+        }
+    }
+}
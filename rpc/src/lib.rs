@@ -0,0 +1,103 @@
+//! Calls between modules. A service is declared with `service!`, which generates a client with
+//! a method per call and an enum of calls for the server to match on. Calls are tagged with ids,
+//! so several may be in flight at once and answered in any order.
+
+use futures::future;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+mod client;
+mod server;
+mod service;
+pub use client::{Client, Driver};
+pub use server::{Incoming, Reply, Server};
+
+#[doc(hidden)]
+pub use futures;
+#[doc(hidden)]
+pub use paste;
+
+/// What goes over the wire, in either direction
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Message<Req, Resp> {
+    /// A call expecting a reply with the same id
+    Call(u64, Req),
+    /// A call without a reply
+    Notify(Req),
+    /// The reply to a call, or `None` if the service dropped it without answering
+    Reply(u64, Option<Resp>),
+}
+
+/// Something which sends and receives whole messages, such as a `loopback::typed::Channel` or a
+/// socket wrapped with `framed`
+pub trait Transport<M>: Sink<M, Error = io::Error> + Stream<Item = io::Result<M>> + Unpin {}
+
+impl<T, M> Transport<M> for T where
+    T: Sink<M, Error = io::Error> + Stream<Item = io::Result<M>> + Unpin
+{
+}
+
+/// Send messages over a byte stream, as length-delimited bincode frames
+pub fn framed<T, M>(io: T) -> impl Transport<M>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    M: Serialize + DeserializeOwned,
+{
+    Framed::new(io, LengthDelimitedCodec::new())
+        .with(|msg: M| {
+            future::ready(
+                bincode::serialize(&msg)
+                    .map(Into::into)
+                    .map_err(invalid_data),
+            )
+        })
+        .map(|frame| bincode::deserialize(&frame?).map_err(invalid_data))
+}
+
+fn invalid_data(e: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Reasons a call can fail
+#[derive(Debug)]
+pub enum Error {
+    /// The transport failed
+    Io(io::Error),
+    /// The connection closed before the reply arrived
+    Disconnected,
+    /// The service dropped the call without answering it
+    Unanswered,
+    /// The peer sent a message that makes no sense here
+    Unexpected,
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "transport failed: {}", e),
+            Error::Disconnected => write!(f, "disconnected before the reply arrived"),
+            Error::Unanswered => write!(f, "the service did not answer"),
+            Error::Unexpected => write!(f, "unexpected message"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
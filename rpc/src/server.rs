@@ -0,0 +1,130 @@
+use crate::{Error, Message, Transport};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::stream::{Stream, StreamExt};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+type ReplyQueue<Resp> = UnboundedSender<(u64, Option<Resp>)>;
+
+/// A call received by a server
+pub struct Incoming<Req, Resp> {
+    pub request: Req,
+    /// Answers the call. Does nothing for notifications.
+    pub reply: Reply<Resp, Resp>,
+}
+
+/// Answers a call with a `T`, which goes over the wire as a `Resp`. May be sent from any task,
+/// in any order. Dropping it unanswered fails the call with `Unanswered`.
+pub struct Reply<T, Resp> {
+    id: Option<u64>,
+    queue: Option<ReplyQueue<Resp>>,
+    wrap: fn(T) -> Resp,
+}
+
+impl<T, Resp> Reply<T, Resp> {
+    /// Answer the call. If the client has gone away, the answer is discarded.
+    pub fn send(mut self, value: T) {
+        if let (Some(id), Some(queue)) = (self.id, self.queue.take()) {
+            let _ = queue.unbounded_send((id, Some((self.wrap)(value))));
+        }
+    }
+
+    /// Returns true if this answers a notification, and so does nothing
+    pub fn is_notification(&self) -> bool {
+        self.id.is_none()
+    }
+
+    /// Answer with a `U` instead
+    pub fn map<U>(mut self, wrap: fn(U) -> Resp) -> Reply<U, Resp> {
+        Reply {
+            id: self.id,
+            queue: self.queue.take(),
+            wrap,
+        }
+    }
+}
+
+impl<T, Resp> Drop for Reply<T, Resp> {
+    fn drop(&mut self) {
+        if let (Some(id), Some(queue)) = (self.id, self.queue.take()) {
+            let _ = queue.unbounded_send((id, None));
+        }
+    }
+}
+
+/// Receives calls made to a service, and sends back their replies while polled
+pub struct Server<T, Req, Resp> {
+    transport: T,
+    queue: ReplyQueue<Resp>,
+    replies: UnboundedReceiver<(u64, Option<Resp>)>,
+    _message: PhantomData<fn() -> Req>,
+}
+
+impl<T, Req, Resp> Server<T, Req, Resp>
+where
+    T: Transport<Message<Req, Resp>>,
+{
+    /// Serve calls arriving over `transport`
+    pub fn new(transport: T) -> Self {
+        let (queue, replies) = unbounded();
+        Self {
+            transport,
+            queue,
+            replies,
+            _message: PhantomData,
+        }
+    }
+
+    fn poll_replies(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        loop {
+            if Pin::new(&mut self.transport).poll_ready(cx)?.is_pending() {
+                break;
+            }
+            // The server holds a sender itself, so the queue never ends
+            let (id, response) = match self.replies.poll_next_unpin(cx) {
+                Poll::Ready(Some(reply)) => reply,
+                _ => break,
+            };
+            Pin::new(&mut self.transport).start_send(Message::Reply(id, response))?;
+        }
+        Pin::new(&mut self.transport)
+            .poll_flush(cx)
+            .map_err(Error::from)
+    }
+
+    fn reply(&self, id: Option<u64>) -> Reply<Resp, Resp> {
+        Reply {
+            id,
+            queue: Some(self.queue.clone()),
+            wrap: |response| response,
+        }
+    }
+}
+
+impl<T, Req, Resp> Stream for Server<T, Req, Resp>
+where
+    T: Transport<Message<Req, Resp>>,
+{
+    type Item = Result<Incoming<Req, Resp>, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if let Poll::Ready(Err(e)) = this.poll_replies(cx) {
+            return Poll::Ready(Some(Err(e)));
+        }
+
+        let (request, id) = match this.transport.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(Message::Call(id, request)))) => (request, Some(id)),
+            Poll::Ready(Some(Ok(Message::Notify(request)))) => (request, None),
+            Poll::Ready(Some(Ok(Message::Reply(..)))) => {
+                return Poll::Ready(Some(Err(Error::Unexpected)))
+            }
+            Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e.into()))),
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => return Poll::Pending,
+        };
+        let reply = this.reply(id);
+        Poll::Ready(Some(Ok(Incoming { request, reply })))
+    }
+}
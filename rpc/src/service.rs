@@ -0,0 +1,168 @@
+/// Declare a service. Calls are written `fn name(args) -> Reply;` and notifications, which get
+/// no reply, `notify name(args);`. For a service `Foo` this generates:
+///
+/// * `FooClient`, with a method per call returning a future of `Result<Reply, rpc::Error>`, and a
+///   method per notification returning once it is queued
+/// * `FooCall`, an enum with a variant per call or notification, for the server to match on.
+///   `FooCall::serve(transport)` gives a stream of them. Call variants have a `reply` field.
+/// * `FooRequest` and `FooResponse`, which go over the wire inside a `FooMessage`
+///
+/// Argument and reply types must implement serde's `Serialize` and `Deserialize`, and the crate
+/// using this macro must depend on serde.
+///
+/// ```ignore
+/// rpc::service! {
+///     pub service Counter {
+///         /// Returns the new count
+///         fn add(amount: u32) -> u32;
+///         notify reset();
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! service {
+    (
+        $(#[$meta:meta])*
+        $vis:vis service $name:ident { $($body:tt)* }
+    ) => {
+        $crate::service!(@parse [$(#[$meta])*] [$vis] $name [] [] $($body)*);
+    };
+
+    (
+        @parse $attrs:tt $vis:tt $name:ident [$($calls:tt)*] $notifies:tt
+        $(#[$m:meta])* fn $method:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty;
+        $($rest:tt)*
+    ) => {
+        $crate::service!(
+            @parse $attrs $vis $name
+            [$($calls)* {[$(#[$m])*] $method [$($arg: $ty),*] $ret}] $notifies
+            $($rest)*
+        );
+    };
+
+    (
+        @parse $attrs:tt $vis:tt $name:ident $calls:tt [$($notifies:tt)*]
+        $(#[$m:meta])* notify $method:ident($($arg:ident: $ty:ty),* $(,)?);
+        $($rest:tt)*
+    ) => {
+        $crate::service!(
+            @parse $attrs $vis $name
+            $calls [$($notifies)* {[$(#[$m])*] $method [$($arg: $ty),*]}]
+            $($rest)*
+        );
+    };
+
+    (
+        @parse [$(#[$meta:meta])*] [$vis:vis] $name:ident
+        [$({[$(#[$cm:meta])*] $call:ident [$($carg:ident: $cty:ty),*] $ret:ty})*]
+        [$({[$(#[$nm:meta])*] $notify:ident [$($narg:ident: $nty:ty),*]})*]
+    ) => { $crate::paste::paste! {
+        /// Calls and notifications sent to the service
+        #[derive(serde::Serialize, serde::Deserialize)]
+        $vis enum [<$name Request>] {
+            $([<$call:camel>]($($cty),*),)*
+            $([<$notify:camel>]($($nty),*),)*
+        }
+
+        /// Replies sent by the service
+        #[derive(serde::Serialize, serde::Deserialize)]
+        $vis enum [<$name Response>] {
+            $([<$call:camel>]($ret),)*
+        }
+
+        /// Everything that goes over the wire for this service
+        $vis type [<$name Message>] = $crate::Message<[<$name Request>], [<$name Response>]>;
+
+        $(#[$meta])*
+        #[derive(Clone)]
+        $vis struct [<$name Client>]($crate::Client<[<$name Request>], [<$name Response>]>);
+
+        impl [<$name Client>] {
+            /// Start talking to the service over `transport`. Nothing is sent or received unless
+            /// the returned driver is polled, so it is usually spawned as its own task.
+            $vis fn new<T>(
+                transport: T,
+            ) -> (Self, $crate::Driver<T, [<$name Request>], [<$name Response>]>)
+            where
+                T: $crate::Transport<[<$name Message>]>,
+            {
+                let (client, driver) = $crate::Client::new(transport);
+                (Self(client), driver)
+            }
+
+            $(
+                $(#[$cm])*
+                $vis fn $call(
+                    &self,
+                    $($carg: $cty),*
+                ) -> impl $crate::futures::Future<Output = Result<$ret, $crate::Error>> {
+                    let response = self.0.call([<$name Request>]::[<$call:camel>]($($carg),*));
+                    async move {
+                        #[allow(unreachable_patterns)]
+                        match response.await? {
+                            [<$name Response>]::[<$call:camel>](value) => Ok(value),
+                            _ => Err($crate::Error::Unexpected),
+                        }
+                    }
+                }
+            )*
+
+            $(
+                $(#[$nm])*
+                $vis fn $notify(&self, $($narg: $nty),*) -> Result<(), $crate::Error> {
+                    self.0.notify([<$name Request>]::[<$notify:camel>]($($narg),*))
+                }
+            )*
+        }
+
+        /// A call or notification received by the service
+        $vis enum [<$name Call>] {
+            $(
+                $(#[$cm])*
+                [<$call:camel>] {
+                    $($carg: $cty,)*
+                    reply: $crate::Reply<$ret, [<$name Response>]>,
+                },
+            )*
+            $(
+                $(#[$nm])*
+                [<$notify:camel>] { $($narg: $nty),* },
+            )*
+        }
+
+        impl [<$name Call>] {
+            /// Serve calls arriving over `transport`. Replies are sent while the stream is polled.
+            $vis fn serve<T>(
+                transport: T,
+            ) -> impl $crate::futures::Stream<Item = Result<Self, $crate::Error>> + Unpin
+            where
+                T: $crate::Transport<[<$name Message>]>,
+            {
+                $crate::futures::StreamExt::map(
+                    $crate::Server::new(transport),
+                    |incoming| incoming.map(Self::from_incoming),
+                )
+            }
+
+            #[allow(unused_variables)]
+            fn from_incoming(
+                incoming: $crate::Incoming<[<$name Request>], [<$name Response>]>,
+            ) -> Self {
+                let $crate::Incoming { request, reply } = incoming;
+                match request {
+                    $(
+                        [<$name Request>]::[<$call:camel>]($($carg),*) => Self::[<$call:camel>] {
+                            $($carg,)*
+                            reply: reply.map([<$name Response>]::[<$call:camel>]),
+                        },
+                    )*
+                    $(
+                        [<$name Request>]::[<$notify:camel>]($($narg),*) => {
+                            Self::[<$notify:camel>] { $($narg),* }
+                        }
+                    )*
+                }
+            }
+        }
+    }};
+}
@@ -0,0 +1,72 @@
+use futures::executor::block_on;
+use futures::{future, StreamExt};
+use loopback::typed::{self, Channel};
+use loopback::Loopback;
+
+rpc::service! {
+    /// Keeps a count
+    pub service Counter {
+        /// Returns the new count
+        fn add(amount: u32) -> u32;
+        /// Never answered
+        fn ignore() -> ();
+        notify reset();
+    }
+}
+
+async fn counter<T: rpc::Transport<CounterMessage>>(transport: T) {
+    let mut count = 0;
+    let mut calls = CounterCall::serve(transport);
+    while let Some(Ok(call)) = calls.next().await {
+        match call {
+            CounterCall::Add { amount, reply } => {
+                count += amount;
+                reply.send(count);
+            }
+            CounterCall::Ignore { .. } => (),
+            CounterCall::Reset {} => count = 0,
+        }
+    }
+}
+
+async fn exercise<T: rpc::Transport<CounterMessage>>(transport: T) {
+    let (client, driver) = CounterClient::new(transport);
+    let session = async move {
+        // Pipelined; both are queued before either reply arrives
+        let (a, b) = future::join(client.add(2), client.add(3)).await;
+        assert_eq!((a.unwrap(), b.unwrap()), (2, 5));
+        client.reset().unwrap();
+        assert_eq!(client.add(1).await.unwrap(), 1);
+        assert!(matches!(client.ignore().await, Err(rpc::Error::Unanswered)));
+    };
+    let (driven, ()) = future::join(driver, session).await;
+    driven.unwrap();
+}
+
+#[test]
+fn typed() {
+    let (client, server) = typed::pair();
+    block_on(future::join(
+        counter(Channel::<CounterMessage, CounterMessage>::typed(server)),
+        exercise(Channel::<CounterMessage, CounterMessage>::typed(client)),
+    ));
+}
+
+#[test]
+fn framed() {
+    let (client, server) = Loopback::pair();
+    block_on(future::join(
+        counter(rpc::framed(server)),
+        exercise(rpc::framed(client)),
+    ));
+}
+
+#[test]
+fn disconnected() {
+    let (client, server) = Loopback::pair();
+    drop(server);
+    let (client, driver) = CounterClient::new(rpc::framed(client));
+    let (driven, reply) = block_on(future::join(driver, client.add(1)));
+    assert!(driven.is_err());
+    assert!(matches!(reply, Err(rpc::Error::Disconnected)));
+}